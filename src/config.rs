@@ -0,0 +1,593 @@
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use reqwest::{
+    Proxy, Url,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+#[derive(Debug)]
+pub struct Config {
+    pub key: String,
+    pub listen_addr: SocketAddr,
+    pub prod_domain: String,
+    pub database_url: String,
+    pub default_model: String,
+    pub allowed_models: Vec<String>,
+    pub model_aliases: HashMap<String, String>,
+    pub completions_url: String,
+    pub upstream_urls: HashMap<String, String>,
+    pub strict_model: bool,
+    pub max_concurrent_upstream: usize,
+    pub queue_wait: Duration,
+    pub daily_token_quota: i64,
+    pub trust_proxy: bool,
+    pub expose_tokens_header: bool,
+    pub circuit_failure_threshold: u32,
+    pub circuit_window: Duration,
+    pub circuit_cooldown: Duration,
+    pub max_messages: usize,
+    pub messages_overflow: MessagesOverflow,
+    pub cache_ttl: Duration,
+    pub redis_url: String,
+    pub startup_probe: bool,
+    pub rate_limit_requests: u32,
+    pub rate_limit_window: Duration,
+    pub model_rate_limits: HashMap<String, u32>,
+    pub estimate_tokens: bool,
+    pub stream_keepalive: Duration,
+    pub extra_upstream_headers: HeaderMap,
+    pub idempotency_ttl: Duration,
+    pub speed_budget: Duration,
+    pub speed_fallback_model: String,
+    pub strip_params: Vec<String>,
+    pub min_temp: Option<f64>,
+    pub max_temp: Option<f64>,
+    pub slow_request: Option<Duration>,
+    pub max_stream: Duration,
+    pub json_unsupported_models: Vec<String>,
+    pub service_notice: String,
+    pub log_sample_rate: f64,
+    pub allowed_service_tiers: Vec<String>,
+    pub geoip_db_path: String,
+    pub geo_allowed_countries: Vec<String>,
+    pub geo_blocked_countries: Vec<String>,
+    pub upstream_proxy: String,
+    pub db_write_attempts: u32,
+    pub content_filter_header: bool,
+    pub admin_cors_origin: String,
+    pub blocked_phrases: Vec<String>,
+    pub blocked_phrases_path: String,
+    pub upstream_retries: u32,
+    pub max_upstream_retries: u32,
+    pub auth_header_name: Option<HeaderName>,
+    pub log_webhook_url: Option<Url>,
+    pub model_defaults: HashMap<String, Map<String, Value>>,
+    pub hmac_secret: String,
+    pub log_retention_days: u32,
+    pub log_retention_interval: Duration,
+    pub max_stop_sequences: usize,
+    pub model_fallbacks: HashMap<String, Vec<String>>,
+    pub forward_headers: Vec<HeaderName>,
+    pub max_metadata_bytes: usize,
+    pub docs_theme: String,
+    pub docs_dark_mode: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessagesOverflow {
+    Reject,
+    Truncate,
+}
+
+impl FromStr for MessagesOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "reject" => Ok(Self::Reject),
+            "truncate" => Ok(Self::Truncate),
+            other => Err(format!("expected reject or truncate, got {other:?}")),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the process environment, which `main` first fills from `.env`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Builds a config from any source of variables, such as a map in tests.
+    /// Unset variables read as empty, the same as a blank line in `.env`.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let var = |name: &str| lookup(name).unwrap_or_default();
+
+        let min_temp = optional("MIN_TEMP", &var("MIN_TEMP"))?;
+        let max_temp = optional("MAX_TEMP", &var("MAX_TEMP"))?;
+        if let (Some(min), Some(max)) = (min_temp, max_temp)
+            && min > max
+        {
+            return Err(invalid(
+                "MIN_TEMP",
+                format!("{min} is above MAX_TEMP {max}"),
+            ));
+        }
+
+        Ok(Self {
+            key: var("KEY").trim().to_string(),
+            listen_addr: listen_addr(&var("BIND_ADDR"), &var("PORT"))?,
+            prod_domain: required("PROD_DOMAIN", &var("PROD_DOMAIN"))?,
+            database_url: var("DATABASE_URL").trim().to_string(),
+            default_model: required("DEFAULT_MODEL", &var("DEFAULT_MODEL"))?,
+            allowed_models: list("ALLOWED_MODELS", &var("ALLOWED_MODELS"))?,
+            model_aliases: json("MODEL_ALIASES", &var("MODEL_ALIASES"))?,
+            completions_url: required("COMPLETIONS_URL", &var("COMPLETIONS_URL"))?,
+            upstream_urls: json("UPSTREAM_URLS", &var("UPSTREAM_URLS"))?,
+            strict_model: flag("STRICT_MODEL", &var("STRICT_MODEL"))?,
+            max_concurrent_upstream: parse_or(
+                "MAX_CONCURRENT_UPSTREAM",
+                &var("MAX_CONCURRENT_UPSTREAM"),
+                64,
+            )?,
+            queue_wait: millis("QUEUE_WAIT_MS", &var("QUEUE_WAIT_MS"), 2000)?,
+            daily_token_quota: parse_or("DAILY_TOKEN_QUOTA", &var("DAILY_TOKEN_QUOTA"), 0)?,
+            trust_proxy: flag("TRUST_PROXY", &var("TRUST_PROXY"))?,
+            expose_tokens_header: flag_or(
+                "EXPOSE_TOKENS_HEADER",
+                &var("EXPOSE_TOKENS_HEADER"),
+                true,
+            )?,
+            circuit_failure_threshold: parse_or(
+                "CIRCUIT_FAILURE_THRESHOLD",
+                &var("CIRCUIT_FAILURE_THRESHOLD"),
+                5,
+            )?,
+            circuit_window: secs("CIRCUIT_WINDOW_SECS", &var("CIRCUIT_WINDOW_SECS"), 30)?,
+            circuit_cooldown: secs("CIRCUIT_COOLDOWN_SECS", &var("CIRCUIT_COOLDOWN_SECS"), 30)?,
+            max_messages: parse_or("MAX_MESSAGES", &var("MAX_MESSAGES"), 0)?,
+            messages_overflow: parse("MESSAGES_OVERFLOW", &var("MESSAGES_OVERFLOW"))?,
+            cache_ttl: secs("CACHE_TTL_SECS", &var("CACHE_TTL_SECS"), 0)?,
+            redis_url: var("REDIS_URL").trim().to_string(),
+            startup_probe: flag("STARTUP_PROBE", &var("STARTUP_PROBE"))?,
+            rate_limit_requests: parse_or("RATE_LIMIT_REQUESTS", &var("RATE_LIMIT_REQUESTS"), 0)?,
+            rate_limit_window: secs("RATE_LIMIT_WINDOW_SECS", &var("RATE_LIMIT_WINDOW_SECS"), 60)?,
+            model_rate_limits: json("MODEL_RATE_LIMITS", &var("MODEL_RATE_LIMITS"))?,
+            estimate_tokens: flag("ESTIMATE_TOKENS", &var("ESTIMATE_TOKENS"))?,
+            stream_keepalive: secs("STREAM_KEEPALIVE_SECS", &var("STREAM_KEEPALIVE_SECS"), 0)?,
+            extra_upstream_headers: headers(
+                "EXTRA_UPSTREAM_HEADERS",
+                &var("EXTRA_UPSTREAM_HEADERS"),
+            )?,
+            idempotency_ttl: secs("IDEMPOTENCY_TTL_SECS", &var("IDEMPOTENCY_TTL_SECS"), 0)?,
+            speed_budget: millis("SPEED_BUDGET_MS", &var("SPEED_BUDGET_MS"), 0)?,
+            speed_fallback_model: var("SPEED_FALLBACK_MODEL").trim().to_string(),
+            strip_params: optional_list(&var("STRIP_PARAMS")),
+            min_temp,
+            max_temp,
+            slow_request: optional("SLOW_REQUEST_MS", &var("SLOW_REQUEST_MS"))?
+                .map(Duration::from_millis),
+            max_stream: secs("MAX_STREAM_SECS", &var("MAX_STREAM_SECS"), 0)?,
+            json_unsupported_models: optional_list(&var("JSON_UNSUPPORTED_MODELS")),
+            service_notice: header_text("SERVICE_NOTICE", &var("SERVICE_NOTICE"))?,
+            log_sample_rate: fraction("LOG_SAMPLE_RATE", &var("LOG_SAMPLE_RATE"))?,
+            allowed_service_tiers: optional_list(&var("ALLOWED_SERVICE_TIERS")),
+            geoip_db_path: var("GEOIP_DB_PATH").trim().to_string(),
+            geo_allowed_countries: optional_list(&var("GEO_ALLOWED_COUNTRIES")),
+            geo_blocked_countries: optional_list(&var("GEO_BLOCKED_COUNTRIES")),
+            upstream_proxy: proxy_url("UPSTREAM_PROXY", &var("UPSTREAM_PROXY"))?,
+            db_write_attempts: parse_or("DB_WRITE_ATTEMPTS", &var("DB_WRITE_ATTEMPTS"), 3)?,
+            content_filter_header: flag("CONTENT_FILTER_HEADER", &var("CONTENT_FILTER_HEADER"))?,
+            admin_cors_origin: header_text("ADMIN_CORS_ORIGIN", &var("ADMIN_CORS_ORIGIN"))?,
+            blocked_phrases: optional_list(&var("BLOCKED_PHRASES")),
+            blocked_phrases_path: var("BLOCKED_PHRASES_PATH").trim().to_string(),
+            upstream_retries: parse_or("UPSTREAM_RETRIES", &var("UPSTREAM_RETRIES"), 0)?,
+            max_upstream_retries: parse_or(
+                "MAX_UPSTREAM_RETRIES",
+                &var("MAX_UPSTREAM_RETRIES"),
+                3,
+            )?,
+            auth_header_name: header_name("AUTH_HEADER_NAME", &var("AUTH_HEADER_NAME"))?,
+            log_webhook_url: optional("LOG_WEBHOOK_URL", &var("LOG_WEBHOOK_URL"))?,
+            model_defaults: json("MODEL_DEFAULTS", &var("MODEL_DEFAULTS"))?,
+            hmac_secret: var("HMAC_SECRET").trim().to_string(),
+            log_retention_days: parse_or("LOG_RETENTION_DAYS", &var("LOG_RETENTION_DAYS"), 0)?,
+            log_retention_interval: secs(
+                "LOG_RETENTION_INTERVAL_SECS",
+                &var("LOG_RETENTION_INTERVAL_SECS"),
+                3600,
+            )?,
+            max_stop_sequences: parse_or("MAX_STOP_SEQUENCES", &var("MAX_STOP_SEQUENCES"), 4)?,
+            model_fallbacks: json("MODEL_FALLBACKS", &var("MODEL_FALLBACKS"))?,
+            forward_headers: header_names("FORWARD_HEADERS", &var("FORWARD_HEADERS"))?,
+            max_metadata_bytes: parse_or("MAX_METADATA_BYTES", &var("MAX_METADATA_BYTES"), 4096)?,
+            docs_theme: var("DOCS_THEME").trim().to_string(),
+            docs_dark_mode: flag("DOCS_DARK_MODE", &var("DOCS_DARK_MODE"))?,
+        })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.key.is_empty()
+    }
+
+    pub fn resolve_model_alias(&self, model: &str) -> Option<&str> {
+        self.model_aliases.get(model).map(String::as_str)
+    }
+
+    pub fn upstream_url(&self, model: &str) -> &str {
+        self.upstream_urls
+            .get(model)
+            .map_or(&self.completions_url, String::as_str)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing(&'static str),
+    Invalid { name: &'static str, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing(name) => write!(f, "{name} must be set"),
+            ConfigError::Invalid { name, reason } => write!(f, "Invalid {name}: {reason}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+fn invalid(name: &'static str, reason: impl fmt::Display) -> ConfigError {
+    ConfigError::Invalid {
+        name,
+        reason: reason.to_string(),
+    }
+}
+
+fn required(name: &'static str, raw: &str) -> Result<String, ConfigError> {
+    match raw.trim() {
+        "" => Err(ConfigError::Missing(name)),
+        value => Ok(value.to_string()),
+    }
+}
+
+fn list(name: &'static str, raw: &str) -> Result<Vec<String>, ConfigError> {
+    let items = optional_list(raw);
+    if items.is_empty() {
+        return Err(ConfigError::Missing(name));
+    }
+
+    Ok(items)
+}
+
+fn optional_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn flag(name: &'static str, raw: &str) -> Result<bool, ConfigError> {
+    flag_or(name, raw, false)
+}
+
+/// Like [`flag`], but an empty value means `default`.
+fn flag_or(name: &'static str, raw: &str, default: bool) -> Result<bool, ConfigError> {
+    match raw.trim() {
+        "" => Ok(default),
+        "false" | "0" => Ok(false),
+        "true" | "1" => Ok(true),
+        other => Err(invalid(
+            name,
+            format!("expected true or false, got {other:?}"),
+        )),
+    }
+}
+
+fn parse<T>(name: &'static str, raw: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    raw.trim().parse().map_err(|e| invalid(name, e))
+}
+
+/// Like [`parse`], but an empty value means `default`.
+fn parse_or<T>(name: &'static str, raw: &str, default: T) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Ok(optional(name, raw)?.unwrap_or(default))
+}
+
+fn optional<T>(name: &'static str, raw: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match raw.trim() {
+        "" => Ok(None),
+        _ => parse(name, raw).map(Some),
+    }
+}
+
+fn fraction(name: &'static str, raw: &str) -> Result<f64, ConfigError> {
+    match optional::<f64>(name, raw)? {
+        None => Ok(1.0),
+        Some(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        Some(rate) => Err(invalid(name, format!("expected 0.0 to 1.0, got {rate}"))),
+    }
+}
+
+fn secs(name: &'static str, raw: &str, default: u64) -> Result<Duration, ConfigError> {
+    parse_or(name, raw, default).map(Duration::from_secs)
+}
+
+fn millis(name: &'static str, raw: &str, default: u64) -> Result<Duration, ConfigError> {
+    parse_or(name, raw, default).map(Duration::from_millis)
+}
+
+fn json<T: DeserializeOwned + Default>(name: &'static str, raw: &str) -> Result<T, ConfigError> {
+    if raw.trim().is_empty() {
+        return Ok(T::default());
+    }
+
+    serde_json::from_str(raw).map_err(|e| invalid(name, e))
+}
+
+fn headers(name: &'static str, raw: &str) -> Result<HeaderMap, ConfigError> {
+    let entries: HashMap<String, String> = json(name, raw)?;
+
+    let mut headers = HeaderMap::new();
+    for (key, value) in entries {
+        let key = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|e| invalid(name, format!("header name {key:?}: {e}")))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|e| invalid(name, format!("value for {key}: {e}")))?;
+        headers.insert(key, value);
+    }
+
+    Ok(headers)
+}
+
+fn header_text(name: &'static str, raw: &str) -> Result<String, ConfigError> {
+    let text = raw.trim();
+    HeaderValue::from_str(text).map_err(|e| invalid(name, e))?;
+    Ok(text.to_string())
+}
+
+fn header_name(name: &'static str, raw: &str) -> Result<Option<HeaderName>, ConfigError> {
+    match raw.trim() {
+        "" => Ok(None),
+        value => HeaderName::from_bytes(value.as_bytes())
+            .map(Some)
+            .map_err(|e| invalid(name, e)),
+    }
+}
+
+fn header_names(name: &'static str, raw: &str) -> Result<Vec<HeaderName>, ConfigError> {
+    optional_list(raw)
+        .iter()
+        .map(|value| HeaderName::from_bytes(value.as_bytes()).map_err(|e| invalid(name, e)))
+        .collect()
+}
+
+fn proxy_url(name: &'static str, raw: &str) -> Result<String, ConfigError> {
+    let url = raw.trim();
+    if !url.is_empty() {
+        Proxy::all(url).map_err(|e| invalid(name, e))?;
+    }
+    Ok(url.to_string())
+}
+
+fn listen_addr(bind: &str, port: &str) -> Result<SocketAddr, ConfigError> {
+    let bind = match bind.trim() {
+        "" => "0.0.0.0",
+        bind => bind,
+    };
+
+    let ip: IpAddr = bind.parse().map_err(|e| invalid("BIND_ADDR", e))?;
+    let port = parse_or("PORT", port, 8080)?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+impl Config {
+    /// `.env.example` with `overrides` applied, for tests. DATABASE_URL is
+    /// cleared first so nothing tries to reach Postgres.
+    pub fn example(overrides: &[(&str, &str)]) -> Result<Self, ConfigError> {
+        let mut vars: HashMap<String, String> =
+            dotenvy::from_read_iter(include_str!("../.env.example").as_bytes())
+                .map(Result::unwrap)
+                .collect();
+        vars.insert("DATABASE_URL".to_string(), String::new());
+        for &(name, value) in overrides {
+            vars.insert(name.to_string(), value.to_string());
+        }
+
+        Self::from_lookup(|name| vars.get(name).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delegates::upstream::build_client;
+
+    #[test]
+    fn env_example_is_a_valid_config() {
+        let config = Config::example(&[]).unwrap();
+        assert_eq!(config.listen_addr, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.resolve_model_alias("qwen"), Some("qwen/qwen3-32b"));
+    }
+
+    #[test]
+    fn reads_variables_from_the_lookup() {
+        let config = Config::example(&[("DEFAULT_MODEL", "llama"), ("STRICT_MODEL", "1")]).unwrap();
+        assert_eq!(config.default_model, "llama");
+        assert!(config.strict_model);
+    }
+
+    #[test]
+    fn tokens_header_is_opt_out() {
+        let unset = Config::example(&[("EXPOSE_TOKENS_HEADER", "")]).unwrap();
+        assert!(unset.expose_tokens_header);
+
+        let off = Config::example(&[("EXPOSE_TOKENS_HEADER", "false")]).unwrap();
+        assert!(!off.expose_tokens_header);
+    }
+
+    #[test]
+    fn listen_addr_defaults_to_all_interfaces() {
+        assert_eq!(
+            listen_addr("", "8080").unwrap(),
+            "0.0.0.0:8080".parse().unwrap()
+        );
+        assert_eq!(
+            listen_addr(" ::1 ", "3000").unwrap(),
+            "[::1]:3000".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn listen_addr_rejects_bad_values() {
+        assert!(matches!(
+            listen_addr("localhost", "8080"),
+            Err(ConfigError::Invalid {
+                name: "BIND_ADDR",
+                ..
+            })
+        ));
+        assert!(matches!(
+            listen_addr("127.0.0.1", "70000"),
+            Err(ConfigError::Invalid { name: "PORT", .. })
+        ));
+    }
+
+    #[test]
+    fn empty_numbers_take_their_defaults() {
+        let blank = [
+            "MAX_CONCURRENT_UPSTREAM",
+            "QUEUE_WAIT_MS",
+            "CIRCUIT_WINDOW_SECS",
+            "RATE_LIMIT_WINDOW_SECS",
+            "DB_WRITE_ATTEMPTS",
+            "MAX_METADATA_BYTES",
+            "LOG_RETENTION_INTERVAL_SECS",
+            "PORT",
+        ]
+        .map(|name| (name, ""));
+        let config = Config::example(&blank).unwrap();
+
+        assert_eq!(config.max_concurrent_upstream, 64);
+        assert_eq!(config.queue_wait, Duration::from_millis(2000));
+        assert_eq!(config.circuit_window, Duration::from_secs(30));
+        assert_eq!(config.rate_limit_window, Duration::from_secs(60));
+        assert_eq!(config.db_write_attempts, 3);
+        assert_eq!(config.max_metadata_bytes, 4096);
+        assert_eq!(config.log_retention_interval, Duration::from_secs(3600));
+        assert_eq!(config.listen_addr.port(), 8080);
+    }
+
+    #[test]
+    fn only_the_required_variables_must_be_set() {
+        let required = [
+            ("DEFAULT_MODEL", "qwen/qwen3-32b"),
+            ("ALLOWED_MODELS", "qwen/qwen3-32b"),
+            (
+                "COMPLETIONS_URL",
+                "https://api.groq.com/openai/v1/chat/completions",
+            ),
+            ("PROD_DOMAIN", "https://ai.hackclub.com"),
+        ];
+        let config = Config::from_lookup(|name| {
+            required
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.to_string())
+        })
+        .unwrap();
+        assert!(!config.is_configured());
+        assert_eq!(config.max_upstream_retries, 3);
+    }
+
+    #[test]
+    fn min_temp_must_not_exceed_max_temp() {
+        let inverted = Config::example(&[("MIN_TEMP", "1.5"), ("MAX_TEMP", "0.5")]);
+        assert!(matches!(
+            inverted,
+            Err(ConfigError::Invalid {
+                name: "MIN_TEMP",
+                ..
+            })
+        ));
+
+        let config = Config::example(&[("MIN_TEMP", "0.5"), ("MAX_TEMP", "0.5")]).unwrap();
+        assert_eq!((config.min_temp, config.max_temp), (Some(0.5), Some(0.5)));
+    }
+
+    #[test]
+    fn extra_upstream_headers_are_validated() {
+        let config = Config::example(&[("EXTRA_UPSTREAM_HEADERS", r#"{"OpenAI-Beta":"v2"}"#)]);
+        assert_eq!(config.unwrap().extra_upstream_headers["openai-beta"], "v2");
+
+        for bad in [r#"{"bad name":"v"}"#, r#"{"X-Ok":"line\nbreak"}"#, "[]"] {
+            let config = Config::example(&[("EXTRA_UPSTREAM_HEADERS", bad)]);
+            assert!(
+                matches!(
+                    config,
+                    Err(ConfigError::Invalid {
+                        name: "EXTRA_UPSTREAM_HEADERS",
+                        ..
+                    })
+                ),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn upstream_proxy_must_be_a_valid_url() {
+        let config =
+            Config::example(&[("UPSTREAM_PROXY", " http://proxy.school.internal:3128 ")]).unwrap();
+        assert_eq!(config.upstream_proxy, "http://proxy.school.internal:3128");
+        assert!(build_client(&config, Default::default()).is_ok());
+
+        let malformed = Config::example(&[("UPSTREAM_PROXY", "http://[::1")]);
+        assert!(matches!(
+            malformed,
+            Err(ConfigError::Invalid {
+                name: "UPSTREAM_PROXY",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_variables() {
+        let missing = Config::example(&[("PROD_DOMAIN", " ")]);
+        assert!(matches!(missing, Err(ConfigError::Missing("PROD_DOMAIN"))));
+
+        let invalid = Config::example(&[("LOG_SAMPLE_RATE", "2")]);
+        assert!(matches!(
+            invalid,
+            Err(ConfigError::Invalid {
+                name: "LOG_SAMPLE_RATE",
+                ..
+            })
+        ));
+    }
+}
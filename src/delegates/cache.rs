@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use redis::{
+    AsyncCommands,
+    aio::{ConnectionManager, ConnectionManagerConfig},
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{error, warn};
+
+use crate::config::Config;
+
+pub trait Cache: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<String>>;
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, ()>;
+}
+
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl Cache for MemoryCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some((expires, value)) if *expires > Instant::now() => Some(value.clone()),
+                Some(_) => {
+                    entries.remove(key);
+                    None
+                }
+                None => None,
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, (expires, _)| *expires > now);
+            entries.insert(key.to_string(), (now + ttl, value));
+        })
+    }
+}
+
+/// Bounds connecting, so an unreachable Redis falls back to no caching within
+/// seconds. The redis crate's default backoff grows a hundredfold per retry
+/// and held up startup for minutes.
+const REDIS_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const REDIS_CONNECT_RETRIES: usize = 1;
+const REDIS_RETRY_MAX_DELAY_MS: u64 = 1_000;
+
+pub struct RedisCache {
+    conn: ConnectionManager,
+}
+
+impl RedisCache {
+    pub async fn connect(url: &str) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(REDIS_CONNECT_TIMEOUT)
+            .set_number_of_retries(REDIS_CONNECT_RETRIES)
+            .set_max_delay(REDIS_RETRY_MAX_DELAY_MS);
+        let conn = ConnectionManager::new_with_config(client, config).await?;
+        Ok(Self { conn })
+    }
+}
+
+impl Cache for RedisCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut conn = self.conn.clone();
+            conn.get(key).await.unwrap_or_else(|e| {
+                error!("Failed to read from Redis cache: {}", e);
+                None
+            })
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let mut conn = self.conn.clone();
+            if let Err(e) = conn
+                .set_ex::<_, _, ()>(key, value, ttl.as_secs().max(1))
+                .await
+            {
+                error!("Failed to write to Redis cache: {}", e);
+            }
+        })
+    }
+}
+
+pub async fn from_config(config: &Config) -> Option<Arc<dyn Cache>> {
+    if config.cache_ttl.is_zero() && config.idempotency_ttl.is_zero() {
+        return None;
+    }
+
+    if config.redis_url.is_empty() {
+        return Some(Arc::new(MemoryCache::default()));
+    }
+
+    match RedisCache::connect(&config.redis_url).await {
+        Ok(cache) => Some(Arc::new(cache)),
+        Err(e) => {
+            warn!("Redis is unreachable, caching disabled: {}", e);
+            None
+        }
+    }
+}
+
+pub fn completion_cache_key(request: &Value) -> Option<String> {
+    let deterministic = request.get("temperature").and_then(Value::as_f64) == Some(0.0);
+    let streaming = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if !deterministic || streaming {
+        return None;
+    }
+
+    let normalized = serde_json::to_vec(request).ok()?;
+    Some(format!("completion:{:x}", Sha256::digest(normalized)))
+}
+
+pub fn idempotency_cache_key(ip: IpAddr, key: &str) -> String {
+    format!("idempotency:{ip}:{:x}", Sha256::digest(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::test_support::{Upstream, ping, post_json, proxy, state};
+
+    #[test]
+    fn only_deterministic_non_streaming_requests_are_cacheable() {
+        let messages = ping();
+        assert!(completion_cache_key(&json!({ "messages": messages, "temperature": 0 })).is_some());
+
+        for request in [
+            json!({ "messages": messages }),
+            json!({ "messages": messages, "temperature": 0.7 }),
+            json!({ "messages": messages, "temperature": 0, "stream": true }),
+        ] {
+            assert_eq!(completion_cache_key(&request), None, "{request}");
+        }
+    }
+
+    #[test]
+    fn cache_keys_depend_on_the_whole_request() {
+        let key = |content: &str| {
+            let messages = json!([{ "role": "user", "content": content }]);
+            completion_cache_key(&json!({ "messages": messages, "temperature": 0 }))
+        };
+        assert_eq!(key("a"), key("a"));
+        assert_ne!(key("a"), key("b"));
+    }
+
+    #[tokio::test]
+    async fn memory_entries_expire_after_their_ttl() {
+        let cache = MemoryCache::default();
+        cache
+            .put("short", "a".to_string(), Duration::from_millis(20))
+            .await;
+        cache
+            .put("long", "b".to_string(), Duration::from_secs(60))
+            .await;
+        assert_eq!(cache.get("short").await.as_deref(), Some("a"));
+
+        sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get("short").await, None);
+        assert_eq!(cache.get("long").await.as_deref(), Some("b"));
+        assert_eq!(cache.get("missing").await, None);
+    }
+
+    #[tokio::test]
+    async fn backend_follows_the_config() {
+        let off = Config::example(&[]).unwrap();
+        assert!(from_config(&off).await.is_none());
+
+        let memory = Config::example(&[("CACHE_TTL_SECS", "60")]).unwrap();
+        assert!(from_config(&memory).await.is_some());
+
+        let unreachable = Config::example(&[
+            ("CACHE_TTL_SECS", "60"),
+            ("REDIS_URL", "redis://127.0.0.1:9"),
+        ])
+        .unwrap();
+        assert!(from_config(&unreachable).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn deterministic_completions_are_served_from_the_cache() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("CACHE_TTL_SECS", "60")]).await).await;
+        let body = json!({ "messages": ping(), "temperature": 0 });
+
+        let first = post_json(proxy, "/chat/completions", body.clone()).await;
+        assert!(first.headers().get("X-Cache").is_none());
+        let first = first.text().await.unwrap();
+
+        let second = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(second.headers()["X-Cache"], "HIT");
+        assert_eq!(second.text().await.unwrap(), first);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_REDIS_URL"]
+    async fn redis_entries_round_trip_and_expire() {
+        let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL must be set");
+        let cache = RedisCache::connect(&url).await.unwrap();
+        let key = format!("hackclub_ai_test:{:016x}", rand::random::<u64>());
+
+        assert_eq!(cache.get(&key).await, None);
+        cache
+            .put(&key, "a".to_string(), Duration::from_secs(1))
+            .await;
+        assert_eq!(cache.get(&key).await.as_deref(), Some("a"));
+
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(cache.get(&key).await, None);
+    }
+}
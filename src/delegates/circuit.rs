@@ -0,0 +1,204 @@
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    failures: AtomicU32,
+    last_failure_at: AtomicU64,
+    opened_at: AtomicU64,
+    /// When the current half-open probe was let through, 0 when none is out.
+    probe_started_at: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            cooldown,
+            failures: AtomicU32::new(0),
+            last_failure_at: AtomicU64::new(0),
+            opened_at: AtomicU64::new(0),
+            probe_started_at: AtomicU64::new(0),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state_at(now_millis())
+    }
+
+    pub fn allow(&self) -> bool {
+        self.allow_at(now_millis())
+    }
+
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        self.opened_at.store(0, Ordering::Relaxed);
+        self.probe_started_at.store(0, Ordering::Release);
+    }
+
+    pub fn record_failure(&self) {
+        self.record_failure_at(now_millis());
+    }
+
+    /// Lets one probe through while half-open. A probe that never reports
+    /// back, say because the client hung up mid-request, stops blocking
+    /// others once a cooldown has passed.
+    fn allow_at(&self, now: u64) -> bool {
+        match self.state_at(now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                let started = self.probe_started_at.load(Ordering::Acquire);
+                let stale = now.saturating_sub(started) >= self.cooldown.as_millis() as u64;
+                (started == 0 || stale)
+                    && self
+                        .probe_started_at
+                        .compare_exchange(started, now, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+            }
+        }
+    }
+
+    fn state_at(&self, now: u64) -> CircuitState {
+        let opened_at = self.opened_at.load(Ordering::Relaxed);
+        if opened_at == 0 {
+            CircuitState::Closed
+        } else if now.saturating_sub(opened_at) < self.cooldown.as_millis() as u64 {
+            CircuitState::Open
+        } else {
+            CircuitState::HalfOpen
+        }
+    }
+
+    fn record_failure_at(&self, now: u64) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let last = self.last_failure_at.swap(now, Ordering::Relaxed);
+        if now.saturating_sub(last) > self.window.as_millis() as u64 {
+            self.failures.store(0, Ordering::Relaxed);
+        }
+
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.threshold || self.state_at(now) == CircuitState::HalfOpen {
+            self.opened_at.store(now, Ordering::Relaxed);
+        }
+        self.probe_started_at.store(0, Ordering::Release);
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(3, Duration::from_secs(30), Duration::from_secs(10))
+    }
+
+    #[test]
+    fn opens_after_threshold_failures_in_window() {
+        let circuit = breaker();
+        circuit.record_failure_at(1_000);
+        circuit.record_failure_at(2_000);
+        assert_eq!(circuit.state_at(2_000), CircuitState::Closed);
+
+        circuit.record_failure_at(3_000);
+        assert_eq!(circuit.state_at(3_000), CircuitState::Open);
+        assert!(!circuit.allow_at(3_000));
+    }
+
+    #[test]
+    fn failures_outside_window_start_over() {
+        let circuit = breaker();
+        circuit.record_failure_at(1_000);
+        circuit.record_failure_at(2_000);
+        circuit.record_failure_at(40_000);
+        assert_eq!(circuit.state_at(40_000), CircuitState::Closed);
+    }
+
+    #[test]
+    fn half_open_lets_one_probe_through() {
+        let circuit = breaker();
+        for now in [1_000, 2_000, 3_000] {
+            circuit.record_failure_at(now);
+        }
+
+        assert_eq!(circuit.state_at(13_000), CircuitState::HalfOpen);
+        assert!(circuit.allow_at(13_000));
+        assert!(!circuit.allow_at(13_500));
+
+        circuit.record_success();
+        assert_eq!(circuit.state_at(14_000), CircuitState::Closed);
+    }
+
+    #[test]
+    fn successful_probe_resets_the_failure_count() {
+        let circuit = breaker();
+        for now in [1_000, 2_000, 3_000] {
+            circuit.record_failure_at(now);
+        }
+
+        assert!(circuit.allow_at(13_000));
+        circuit.record_success();
+        circuit.record_failure_at(14_000);
+        circuit.record_failure_at(15_000);
+        assert_eq!(circuit.state_at(15_000), CircuitState::Closed);
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let circuit = breaker();
+        for now in [1_000, 2_000, 3_000] {
+            circuit.record_failure_at(now);
+        }
+
+        assert!(circuit.allow_at(13_000));
+        circuit.record_failure_at(13_100);
+        assert_eq!(circuit.state_at(13_200), CircuitState::Open);
+    }
+
+    #[test]
+    fn abandoned_probe_expires_after_cooldown() {
+        let circuit = breaker();
+        for now in [1_000, 2_000, 3_000] {
+            circuit.record_failure_at(now);
+        }
+
+        assert!(circuit.allow_at(13_000));
+        assert!(!circuit.allow_at(22_999));
+        assert!(circuit.allow_at(23_000));
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let circuit = CircuitBreaker::new(0, Duration::from_secs(30), Duration::from_secs(10));
+        for now in 1..100 {
+            circuit.record_failure_at(now);
+        }
+        assert!(circuit.allow_at(100));
+    }
+}
@@ -0,0 +1,100 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, StatusCode, request::Parts},
+};
+
+use crate::{
+    delegates::error::{APIError, ErrorKind},
+    metrics::database::MetricsState,
+};
+
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequestParts<MetricsState> for ClientIp {
+    type Rejection = APIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &MetricsState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.config.trust_proxy
+            && let Some(ip) = forwarded_ip(&parts.headers)
+        {
+            return Ok(ClientIp(ip));
+        }
+
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| ClientIp(addr.ip()))
+            .ok_or(APIError {
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+                kind: ErrorKind::Internal,
+                body: Some("Missing connection info".into()),
+            })
+    }
+}
+
+/// Clients can prepend anything to `X-Forwarded-For`, so only the entry our
+/// proxy appended (the rightmost) is trusted, after the proxy's own
+/// `X-Real-IP`.
+fn forwarded_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    header("x-real-ip")
+        .and_then(|ip| ip.trim().parse().ok())
+        .or_else(|| {
+            header("x-forwarded-for")
+                .and_then(|v| v.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn takes_rightmost_forwarded_entry() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1, 10.0.0.1, 203.0.113.7")]);
+        assert_eq!(forwarded_ip(&headers), Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn prefers_real_ip() {
+        let headers = headers(&[
+            ("x-forwarded-for", "1.1.1.1, 203.0.113.7"),
+            ("x-real-ip", "198.51.100.2"),
+        ]);
+        assert_eq!(
+            forwarded_ip(&headers),
+            Some("198.51.100.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn falls_back_when_real_ip_is_invalid() {
+        let headers = headers(&[("x-real-ip", "nonsense"), ("x-forwarded-for", "::1")]);
+        assert_eq!(forwarded_ip(&headers), Some("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(
+            forwarded_ip(&headers(&[("x-forwarded-for", "1.1.1.1, bogus")])),
+            None
+        );
+        assert_eq!(forwarded_ip(&HeaderMap::new()), None);
+    }
+}
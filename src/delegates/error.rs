@@ -1,7 +1,9 @@
 use std::{
+    borrow::Cow,
     error::Error,
     fmt,
-    io::{Error as IoError, ErrorKind},
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use axum::{
@@ -9,30 +11,107 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use serde_json::json;
-use tracing::error;
+use serde_json::{Value, json};
+use tracing::{error, info, warn};
+
+static ERROR_COUNTS: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+
+/// Who is at fault, which decides the log level and the metrics label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Client,
+    Upstream,
+    Internal,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 3] = [ErrorKind::Client, ErrorKind::Upstream, ErrorKind::Internal];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Client => "client",
+            ErrorKind::Upstream => "upstream",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    /// Errors of this kind returned since startup.
+    pub fn count(self) -> u64 {
+        ERROR_COUNTS[self as usize].load(Ordering::Relaxed)
+    }
+
+    fn record(self, reason: &str) {
+        ERROR_COUNTS[self as usize].fetch_add(1, Ordering::Relaxed);
+        match self {
+            ErrorKind::Client => info!("Client error: {reason}"),
+            ErrorKind::Upstream => warn!("Upstream error: {reason}"),
+            ErrorKind::Internal => error!("Internal error: {reason}"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct APIError {
     pub code: StatusCode,
-    pub body: Option<&'static str>,
+    pub kind: ErrorKind,
+    pub body: Option<Cow<'static, str>>,
 }
 
 impl IntoResponse for APIError {
     fn into_response(self) -> Response<Body> {
         let reason = self
             .body
+            .as_deref()
             .or(self.code.canonical_reason())
             .unwrap_or("Unknown error");
-        error!("Status code based error: {reason}");
+        self.kind.record(reason);
 
-        let body: Body = json!({ "error": reason }).to_string().into();
+        error_response(self.code, reason, None)
+    }
+}
 
-        Response::builder()
-            .status(self.code)
-            .header("Content-Type", "application/json")
-            .body(body)
-            .unwrap()
+/// Every problem found while validating a request, reported together under
+/// `error.details` with the joined list as the message.
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<String>);
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response<Body> {
+        let message = self.0.join("; ");
+        ErrorKind::Client.record(&message);
+
+        error_response(StatusCode::BAD_REQUEST, &message, Some(self.0))
+    }
+}
+
+fn error_response(code: StatusCode, message: &str, details: Option<Vec<String>>) -> Response<Body> {
+    let mut error = json!({
+        "message": message,
+        "type": error_type(code),
+        "param": null,
+        "code": null,
+    });
+    if let Some(details) = details {
+        error["details"] = Value::from(details);
+    }
+
+    let body: Body = json!({ "error": error }).to_string().into();
+
+    Response::builder()
+        .status(code)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+fn error_type(code: StatusCode) -> &'static str {
+    match code {
+        StatusCode::UNAUTHORIZED => "authentication_error",
+        StatusCode::FORBIDDEN => "permission_error",
+        StatusCode::NOT_FOUND => "not_found_error",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+        code if code.is_server_error() => "api_error",
+        _ => "invalid_request_error",
     }
 }
 
@@ -41,14 +120,15 @@ impl From<Box<dyn Error + Send + Sync + 'static>> for APIError {
         error!("API Error: {err}");
         APIError {
             code: StatusCode::INTERNAL_SERVER_ERROR,
-            body: Some("Internal server error"),
+            kind: ErrorKind::Internal,
+            body: Some("Internal server error".into()),
         }
     }
 }
 
 impl fmt::Display for APIError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.body.unwrap_or("Unknown error"))
+        write!(f, "{}", self.body.as_deref().unwrap_or("Unknown error"))
     }
 }
 
@@ -56,6 +136,148 @@ impl Error for APIError {}
 
 impl From<APIError> for IoError {
     fn from(api_error: APIError) -> Self {
-        IoError::new(ErrorKind::Other, api_error.body.unwrap_or("Unknown error"))
+        IoError::new(
+            IoErrorKind::Other,
+            api_error.body.unwrap_or(Cow::Borrowed("Unknown error")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::test_support::capture_logs;
+
+    /// The error object the OpenAI SDKs parse.
+    #[derive(Deserialize)]
+    struct Envelope {
+        error: OpenAIError,
+    }
+
+    #[derive(Deserialize)]
+    struct OpenAIError {
+        message: String,
+        #[serde(rename = "type")]
+        kind: String,
+        param: Option<String>,
+        code: Option<String>,
+        #[serde(default)]
+        details: Vec<String>,
+    }
+
+    async fn envelope(response: Response) -> Envelope {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_use_the_openai_envelope() {
+        let response = APIError {
+            code: StatusCode::TOO_MANY_REQUESTS,
+            kind: ErrorKind::Client,
+            body: Some("Slow down".into()),
+        }
+        .into_response();
+        assert_eq!(response.headers()["Content-Type"], "application/json");
+
+        let error = envelope(response).await.error;
+        assert_eq!(error.message, "Slow down");
+        assert_eq!(error.kind, "rate_limit_error");
+        assert_eq!((error.param, error.code), (None, None));
+        assert!(error.details.is_empty());
+    }
+
+    #[tokio::test]
+    async fn validation_errors_list_every_problem() {
+        let problems = vec!["first".to_string(), "second".to_string()];
+        let response = ValidationErrors(problems.clone()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let error = envelope(response).await.error;
+        assert_eq!(error.message, "first; second");
+        assert_eq!(error.kind, "invalid_request_error");
+        assert_eq!(error.details, problems);
+    }
+
+    #[tokio::test]
+    async fn missing_bodies_fall_back_to_the_status_reason() {
+        let response = APIError {
+            code: StatusCode::SERVICE_UNAVAILABLE,
+            kind: ErrorKind::Internal,
+            body: None,
+        }
+        .into_response();
+
+        let error = envelope(response).await.error;
+        assert_eq!(error.message, "Service Unavailable");
+        assert_eq!(error.kind, "api_error");
+    }
+
+    #[test]
+    fn error_types_follow_the_status() {
+        assert_eq!(error_type(StatusCode::UNAUTHORIZED), "authentication_error");
+        assert_eq!(error_type(StatusCode::FORBIDDEN), "permission_error");
+        assert_eq!(error_type(StatusCode::NOT_FOUND), "not_found_error");
+        assert_eq!(error_type(StatusCode::BAD_GATEWAY), "api_error");
+        assert_eq!(
+            error_type(StatusCode::PAYLOAD_TOO_LARGE),
+            "invalid_request_error"
+        );
+    }
+
+    #[test]
+    fn responses_are_counted_by_kind() {
+        // The counters are process-wide and other tests run in parallel, so
+        // only a lower bound holds.
+        let before = ErrorKind::ALL.map(ErrorKind::count);
+        for kind in ErrorKind::ALL {
+            let _ = APIError {
+                code: StatusCode::BAD_GATEWAY,
+                kind,
+                body: None,
+            }
+            .into_response();
+        }
+        let _ = ValidationErrors(vec!["bad".to_string()]).into_response();
+
+        let after = ErrorKind::ALL.map(ErrorKind::count);
+        assert!(after[0] >= before[0] + 2);
+        assert!(after[1] > before[1]);
+        assert!(after[2] > before[2]);
+    }
+
+    #[test]
+    fn log_level_follows_the_kind() {
+        let logs = capture_logs();
+        for (kind, body) in [
+            (ErrorKind::Client, "bad input"),
+            (ErrorKind::Upstream, "provider down"),
+            (ErrorKind::Internal, "pool exhausted"),
+        ] {
+            let _ = APIError {
+                code: StatusCode::BAD_GATEWAY,
+                kind,
+                body: Some(body.into()),
+            }
+            .into_response();
+        }
+
+        assert_eq!(
+            logs.lines_with(&["INFO", "Client error: bad input"]).len(),
+            1
+        );
+        assert_eq!(
+            logs.lines_with(&["WARN", "Upstream error: provider down"])
+                .len(),
+            1
+        );
+        assert_eq!(
+            logs.lines_with(&["ERROR", "Internal error: pool exhausted"])
+                .len(),
+            1
+        );
     }
 }
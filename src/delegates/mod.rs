@@ -1 +1,7 @@
+pub mod cache;
+pub mod circuit;
+pub mod client_ip;
 pub mod error;
+pub mod sse;
+pub mod timing;
+pub mod upstream;
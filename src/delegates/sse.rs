@@ -0,0 +1,203 @@
+use serde_json::Value;
+
+use crate::metrics::database::{TokenSource, estimate_from_chars, extract_tokens, finish_reason};
+
+#[derive(Default)]
+pub struct UsageAccumulator {
+    pending: Vec<u8>,
+    usage: Option<Value>,
+    last: Option<Value>,
+    prompt_chars: usize,
+    content_chars: usize,
+    finish_reason: Option<String>,
+    done: bool,
+}
+
+/// What a relayed stream amounts to once it ends, cleanly or not.
+pub struct StreamSummary {
+    /// The usage event, or the last event when none arrived.
+    pub response: Value,
+    pub tokens: Option<(i32, TokenSource)>,
+    pub finish_reason: Option<String>,
+    /// Whether the upstream got as far as `[DONE]`.
+    pub completed: bool,
+}
+
+impl UsageAccumulator {
+    /// Starts counting a stream for `request`, whose message text is part of
+    /// the estimate when the upstream never reports usage.
+    pub fn for_request(request: &Value) -> Self {
+        Self {
+            prompt_chars: prompt_chars(request),
+            ..Self::default()
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(chunk);
+
+        let mut start = 0;
+        while let Some(len) = pending[start..].iter().position(|&b| b == b'\n') {
+            self.observe(&pending[start..start + len]);
+            start += len + 1;
+        }
+
+        pending.drain(..start);
+        self.pending = pending;
+    }
+
+    /// Summarizes the stream for logging, or `None` if no event arrived.
+    /// When the upstream never sent a usage event and `estimate` is set
+    /// (ESTIMATE_TOKENS), tokens are estimated from the prompt's message text
+    /// plus the streamed `delta.content`.
+    pub fn finish(mut self, estimate: bool) -> Option<StreamSummary> {
+        let rest = std::mem::take(&mut self.pending);
+        self.observe(&rest);
+
+        let (response, tokens) = match self.usage {
+            Some(usage) => {
+                let counted = extract_tokens(&usage, false);
+                (usage, counted)
+            }
+            None => {
+                let estimated = estimate
+                    .then(|| estimate_from_chars(self.prompt_chars + self.content_chars))
+                    .flatten();
+                (self.last?, estimated.map(|t| (t, TokenSource::Estimated)))
+            }
+        };
+
+        Some(StreamSummary {
+            response,
+            tokens,
+            finish_reason: self.finish_reason,
+            completed: self.done,
+        })
+    }
+
+    fn observe(&mut self, line: &[u8]) {
+        let Some(data) = line.strip_prefix(b"data:").map(<[u8]>::trim_ascii) else {
+            return;
+        };
+        if data == b"[DONE]" {
+            self.done = true;
+            return;
+        }
+        let Ok(event) = serde_json::from_slice::<Value>(data) else {
+            return;
+        };
+
+        self.content_chars += delta_chars(&event);
+        if let Some(reason) = finish_reason(&event) {
+            self.finish_reason = Some(reason.to_string());
+        }
+        if extract_tokens(&event, false).is_some() {
+            self.usage = Some(event);
+        } else {
+            self.last = Some(event);
+        }
+    }
+}
+
+fn delta_chars(event: &Value) -> usize {
+    event
+        .get("choices")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|choice| choice.get("delta")?.get("content")?.as_str())
+        .map(|content| content.chars().count())
+        .sum()
+}
+
+/// Characters of message text in a chat request, counting both plain string
+/// content and the `text` of content parts.
+fn prompt_chars(request: &Value) -> usize {
+    let text_chars = |content: &Value| match content {
+        Value::String(text) => text.chars().count(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text")?.as_str())
+            .map(|text| text.chars().count())
+            .sum(),
+        _ => 0,
+    };
+
+    request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message.get("content"))
+        .map(text_chars)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn streamed(chunks: &[&str]) -> UsageAccumulator {
+        let mut usage = UsageAccumulator::default();
+        for chunk in chunks {
+            usage.feed(chunk.as_bytes());
+        }
+        usage
+    }
+
+    #[test]
+    fn reads_usage_split_across_chunks() {
+        let usage = streamed(&[
+            "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\nda",
+            "ta: {\"choices\":[],\"usage\":{\"total_tokens\":",
+            "42}}\n\ndata: [DONE]\n\n",
+        ]);
+
+        let summary = usage.finish(true).unwrap();
+        assert_eq!(summary.tokens, Some((42, TokenSource::Usage)));
+        assert!(summary.completed);
+    }
+
+    #[test]
+    fn estimates_without_a_usage_event() {
+        let usage = streamed(&[
+            "data: {\"choices\":[{\"delta\":{\"content\":\"abcdefgh\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        ]);
+
+        let summary = usage.finish(true).unwrap();
+        assert_eq!(summary.tokens, Some((2, TokenSource::Estimated)));
+        assert_eq!(summary.finish_reason.as_deref(), Some("stop"));
+        assert!(!summary.completed);
+    }
+
+    #[test]
+    fn estimate_includes_the_prompt() {
+        let request = json!({
+            "messages": [
+                { "role": "system", "content": "abcd" },
+                { "role": "user", "content": [{ "type": "text", "text": "abcd" }] },
+            ]
+        });
+        let mut usage = UsageAccumulator::for_request(&request);
+        usage.feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"abcd\"}}]}\n");
+
+        let summary = usage.finish(true).unwrap();
+        assert_eq!(summary.tokens, Some((3, TokenSource::Estimated)));
+    }
+
+    #[test]
+    fn respects_estimate_tokens_off() {
+        let usage = streamed(&["data: {\"choices\":[{\"delta\":{\"content\":\"abcd\"}}]}\n"]);
+
+        assert_eq!(usage.finish(false).unwrap().tokens, None);
+    }
+
+    #[test]
+    fn nothing_to_log_without_events() {
+        assert!(streamed(&[": keepalive\n\n"]).finish(true).is_none());
+    }
+}
@@ -0,0 +1,12 @@
+use std::time::Instant;
+
+use tracing::{Instrument, Span};
+
+/// Runs `fut` inside `span` and records how long it took in the span's
+/// `elapsed_ms` field, which is printed when the span closes.
+pub async fn timed<F: Future>(span: Span, fut: F) -> F::Output {
+    let started = Instant::now();
+    let output = fut.instrument(span.clone()).await;
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+    output
+}
@@ -0,0 +1,229 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use reqwest::{
+    Client, Method, Proxy, Request,
+    header::{self, HeaderMap, HeaderValue},
+};
+use serde_json::Value;
+use tower::util::MapRequestLayer;
+
+use crate::config::Config;
+
+/// Connections a client opened and completions sent through it, behind
+/// `connection_reuse_ratio` on /stats.
+#[derive(Default)]
+pub struct ConnectionStats {
+    opened: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl ConnectionStats {
+    pub fn count_request(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Share of completions that went out on a pooled connection instead of
+    /// a new one, or `None` before the first. Probes, pings and log
+    /// webhooks share the client and its pool without being counted, so
+    /// connections they open lower the ratio and ones they leave idle can
+    /// raise it.
+    pub fn reuse_ratio(&self) -> Option<f64> {
+        let requests = self.sent.load(Ordering::Relaxed);
+        if requests == 0 {
+            return None;
+        }
+
+        let opened = self.opened.load(Ordering::Relaxed).min(requests);
+        Some(1.0 - opened as f64 / requests as f64)
+    }
+}
+
+pub(crate) fn build_client(
+    config: &Config,
+    connections: Arc<ConnectionStats>,
+) -> reqwest::Result<Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        header::USER_AGENT,
+        HeaderValue::from_static("hackclub-ai-proxy/1.0"),
+    );
+
+    // reqwest only calls its connector when the pool has no idle connection
+    // to the host, so counting calls counts newly established connections.
+    let mut builder =
+        Client::builder()
+            .default_headers(headers)
+            .connector_layer(MapRequestLayer::new(move |request| {
+                connections.opened.fetch_add(1, Ordering::Relaxed);
+                request
+            }));
+    if !config.upstream_proxy.is_empty() {
+        builder = builder.proxy(Proxy::all(&config.upstream_proxy)?);
+    }
+
+    builder.build()
+}
+
+pub(crate) fn models_url(config: &Config) -> String {
+    let base = config
+        .completions_url
+        .trim_end_matches('/')
+        .trim_end_matches("/chat/completions");
+    format!("{base}/models")
+}
+
+pub(crate) fn build_upstream_request(
+    client: &Client,
+    config: &Config,
+    model: &str,
+    body: &Value,
+) -> reqwest::Result<Request> {
+    client
+        .request(Method::POST, config.upstream_url(model))
+        .bearer_auth(&config.key)
+        .headers(config.extra_upstream_headers.clone())
+        .json(body)
+        .build()
+}
+
+pub(crate) async fn probe_upstream(client: &Client, config: &Config) -> Result<(), String> {
+    let response = client
+        .get(models_url(config))
+        .bearer_auth(&config.key)
+        .send()
+        .await
+        .map_err(|e| format!("failed to reach upstream: {e}"))?;
+
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(format!("upstream responded with {status}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{Upstream, ping, post_json, proxy, state};
+
+    const OSS_URL: &str = "http://oss.internal/v1/chat/completions";
+
+    fn request(config: &Config, model: &str) -> Request {
+        let client = build_client(config, Arc::default()).unwrap();
+        build_upstream_request(&client, config, model, &json!({ "model": model })).unwrap()
+    }
+
+    #[test]
+    fn routes_each_model_to_its_upstream() {
+        let urls = format!(r#"{{"openai/gpt-oss-120b":"{OSS_URL}"}}"#);
+        let config = Config::example(&[("UPSTREAM_URLS", &urls)]).unwrap();
+
+        let routed = request(&config, "openai/gpt-oss-120b");
+        assert_eq!(routed.method(), Method::POST);
+        assert_eq!(routed.url().as_str(), OSS_URL);
+
+        let default = request(&config, "qwen/qwen3-32b");
+        assert_eq!(default.url().as_str(), config.completions_url);
+    }
+
+    #[test]
+    fn authenticates_with_the_service_key() {
+        let config = Config::example(&[]).unwrap();
+        let request = request(&config, "qwen/qwen3-32b");
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Bearer key");
+    }
+
+    #[test]
+    fn adds_the_extra_upstream_headers() {
+        let extra = r#"{"OpenAI-Beta":"assistants=v2","OpenAI-Organization":"org-hc"}"#;
+        let config = Config::example(&[("EXTRA_UPSTREAM_HEADERS", extra)]).unwrap();
+        let request = request(&config, "qwen/qwen3-32b");
+        assert_eq!(request.headers()["openai-beta"], "assistants=v2");
+        assert_eq!(request.headers()["openai-organization"], "org-hc");
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Bearer key");
+    }
+
+    #[test]
+    fn auth_header_name_only_applies_to_inbound_requests() {
+        let config = Config::example(&[("AUTH_HEADER_NAME", "X-School-Key")]).unwrap();
+        let request = request(&config, "qwen/qwen3-32b");
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Bearer key");
+        assert!(!request.headers().contains_key("x-school-key"));
+    }
+
+    #[tokio::test]
+    async fn probe_succeeds_against_a_models_endpoint() {
+        let upstream = Upstream::start().await;
+        let config = Config::example(&[("COMPLETIONS_URL", &upstream.url())]).unwrap();
+        let client = build_client(&config, Arc::default()).unwrap();
+
+        assert_eq!(probe_upstream(&client, &config).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn probe_reports_error_statuses_and_unreachable_hosts() {
+        let upstream = Upstream::start().await;
+        let missing = format!("http://{}/v2/chat/completions", upstream.addr);
+        let config = Config::example(&[("COMPLETIONS_URL", &missing)]).unwrap();
+        let client = build_client(&config, Arc::default()).unwrap();
+        assert_eq!(
+            probe_upstream(&client, &config).await,
+            Err("upstream responded with 404 Not Found".to_string())
+        );
+
+        let closed = "http://127.0.0.1:9/v1/chat/completions";
+        let config = Config::example(&[("COMPLETIONS_URL", closed)]).unwrap();
+        let error = probe_upstream(&client, &config).await.unwrap_err();
+        assert!(error.starts_with("failed to reach upstream"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn sends_through_the_upstream_proxy() {
+        // The mock answers the absolute-form request a forward proxy receives.
+        let forward_proxy = Upstream::start().await;
+        let proxy_url = format!("http://{}", forward_proxy.addr);
+        let config = Config::example(&[
+            (
+                "COMPLETIONS_URL",
+                "http://upstream.invalid/v1/chat/completions",
+            ),
+            ("UPSTREAM_PROXY", &proxy_url),
+        ])
+        .unwrap();
+        let client = build_client(&config, Arc::default()).unwrap();
+
+        let response = client
+            .execute(request(&config, "qwen/qwen3-32b"))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let sent = forward_proxy.requests();
+        assert_eq!(sent[0].headers[header::HOST], "upstream.invalid");
+        assert_eq!(sent[0].headers[header::AUTHORIZATION], "Bearer key");
+    }
+
+    #[tokio::test]
+    async fn reuse_ratio_counts_completions_sent_over_pooled_connections() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+
+        // Building a request is not sending it.
+        request(&state.config, "qwen/qwen3-32b");
+        assert_eq!(state.connections.reuse_ratio(), None);
+
+        let proxy = proxy(state.clone()).await;
+        for _ in 0..4 {
+            post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        }
+        assert_eq!(state.connections.reuse_ratio(), Some(0.75));
+    }
+}
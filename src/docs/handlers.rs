@@ -1,13 +1,26 @@
 use axum::{
     Json,
+    extract::State,
+    http::{StatusCode, header},
     response::{Html, IntoResponse},
 };
-use maud::html;
-use utoipa::{OpenApi, openapi::ServerBuilder};
+use maud::{PreEscaped, html};
+use serde_json::json;
+use tracing::error;
+use utoipa::{
+    OpenApi,
+    openapi::{self, ServerBuilder},
+};
+
+use crate::{ApiDoc, config::Config, metrics::database::MetricsState};
+
+/// Hack Club red on the Scalar palette, used for `DOCS_THEME=hackclub`.
+const HACK_CLUB_CSS: &str = ".light-mode { --scalar-color-accent: #ec3750; }
+.dark-mode { --scalar-color-accent: #ec3750; --scalar-background-1: #17171d; --scalar-background-2: #252429; }";
 
-use crate::{ApiDoc, PROD_DOMAIN};
+pub async fn docs(State(state): State<MetricsState>) -> impl IntoResponse {
+    let options = scalar_options(&state.config);
 
-pub async fn docs() -> impl IntoResponse {
     Html(html! {
 		html {
 			head {
@@ -16,19 +29,132 @@ pub async fn docs() -> impl IntoResponse {
 			}
 			body {
 				div id="app" {}
-				script { "const app = Scalar.createApiReference('#app', { url: '/openapi.json', hideDownloadButton: true, hideClientButton: true, hideModels: true });" }
+				script { (PreEscaped(format!("const app = Scalar.createApiReference('#app', {options});"))) }
 			}
 		}
 	}.into_string())
 }
 
-pub async fn openapi_axle() -> impl IntoResponse {
+pub async fn openapi_axle(State(state): State<MetricsState>) -> impl IntoResponse {
+    Json(document(&state))
+}
+
+pub async fn openapi_yaml(State(state): State<MetricsState>) -> impl IntoResponse {
+    match document(&state).to_yaml() {
+        Ok(yaml) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/yaml")],
+            yaml,
+        ),
+        Err(e) => {
+            error!("Failed to serialize OpenAPI document as YAML: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                "Failed to render OpenAPI document".to_string(),
+            )
+        }
+    }
+}
+
+/// Options for `Scalar.createApiReference`. DOCS_THEME names a Scalar theme,
+/// or `hackclub` for our own colors; DOCS_DARK_MODE opens in dark mode.
+fn scalar_options(config: &Config) -> String {
+    let mut options = json!({
+        "url": "/openapi.json",
+        "hideDownloadButton": true,
+        "hideClientButton": true,
+        "hideModels": true,
+        "darkMode": config.docs_dark_mode,
+    });
+
+    match config.docs_theme.as_str() {
+        "" => {}
+        "hackclub" => {
+            options["theme"] = "none".into();
+            options["customCss"] = HACK_CLUB_CSS.into();
+        }
+        theme => options["theme"] = theme.into(),
+    }
+
+    // Keep the inline script from being closed early by a configured value.
+    options.to_string().replace("</", "<\\/")
+}
+
+fn document(state: &MetricsState) -> openapi::OpenApi {
     let mut openapi = ApiDoc::openapi();
     openapi.servers = Some(vec![
         ServerBuilder::new()
-            .url(PROD_DOMAIN)
+            .url(&state.config.prod_domain)
             .description(Some("Production"))
             .build(),
     ]);
-    Json(openapi)
+    openapi
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_support::{Upstream, proxy, state};
+
+    /// The options object the rendered /docs page passes to Scalar.
+    async fn rendered_options(overrides: &[(&str, &str)]) -> Value {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, overrides).await).await;
+
+        let html = reqwest::get(format!("http://{proxy}/docs"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let (_, script) = html.split_once("createApiReference('#app', ").unwrap();
+        let (options, _) = script.split_once(");</script>").unwrap();
+        serde_json::from_str(options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn docs_page_carries_the_configured_theme() {
+        let options = rendered_options(&[]).await;
+        assert!(options.get("theme").is_none());
+        assert_eq!(options["darkMode"], false);
+
+        let themed = [("DOCS_THEME", "purple"), ("DOCS_DARK_MODE", "true")];
+        let options = rendered_options(&themed).await;
+        assert_eq!(options["theme"], "purple");
+        assert_eq!(options["darkMode"], true);
+
+        let options = rendered_options(&[("DOCS_THEME", "hackclub")]).await;
+        assert_eq!(options["theme"], "none");
+        assert_eq!(options["customCss"], HACK_CLUB_CSS);
+    }
+
+    #[tokio::test]
+    async fn configured_values_cannot_close_the_script() {
+        let options = rendered_options(&[("DOCS_THEME", "</script><b>")]).await;
+        assert_eq!(options["theme"], "</script><b>");
+    }
+
+    #[tokio::test]
+    async fn yaml_is_the_same_document_as_json() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let json: Value = reqwest::get(format!("http://{proxy}/openapi.json"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let response = reqwest::get(format!("http://{proxy}/openapi.yaml"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "application/yaml");
+        let yaml: Value = serde_norway::from_str(&response.text().await.unwrap()).unwrap();
+
+        assert_eq!(yaml, json);
+        assert_eq!(yaml["servers"][0]["url"], "https://ai.hackclub.com");
+    }
 }
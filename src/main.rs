@@ -1,57 +1,81 @@
+#![recursion_limit = "256"]
+
+mod config;
 mod delegates;
 mod docs;
 mod metrics;
 mod routes;
+#[cfg(test)]
+mod test_support;
 
-use std::{collections::HashSet, net::SocketAddr, sync::LazyLock, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     Router,
-    http::header,
+    http::{HeaderValue, Method, header},
     middleware,
     routing::{get, post},
 };
-use dotenvy_macro::dotenv;
-use reqwest::{
-    Client, StatusCode,
-    header::{HeaderMap, HeaderValue},
-};
 use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::fmt;
+use tracing::{info, warn};
+use tracing_subscriber::fmt::{self, format::FmtSpan};
 use utoipa::OpenApi;
 
 use crate::{
-    delegates::error::APIError,
-    docs::handlers::{docs, openapi_axle},
-    metrics::{database::MetricsState, index::index},
+    config::Config,
+    delegates::upstream::{ConnectionStats, build_client, probe_upstream},
+    docs::handlers::{docs, openapi_axle, openapi_yaml},
+    metrics::{
+        access_log::access_log,
+        cleanup::spawn_retention,
+        database::MetricsState,
+        health::{ping, readyz, status},
+        index::index,
+        prometheus::prometheus,
+        stats::stats,
+        usage::usage,
+    },
     routes::{
-        completions::{completions, validate_model},
+        admin::{debug_config, export_logs, replay_log, require_key, set_maintenance, set_models},
+        batch::batch_completions,
+        compat::{anthropic_messages, legacy_completions},
+        completions::{completions, service_notice, validate_model},
+        geo::enforce_geoblock,
         legacy::{echo, get_model, manual_hello},
+        limits::{enforce_maintenance, enforce_rate_limit, enforce_token_quota},
+        not_found::not_found,
+        signature::verify_signature,
+        websocket::ws_completions,
     },
 };
 
-pub(crate) const KEY: &str = dotenv!("KEY");
-pub(crate) const PORT: &str = dotenv!("PORT");
-pub(crate) const PROD_DOMAIN: &str = dotenv!("PROD_DOMAIN");
-pub(crate) const DATABASE_URL: &str = dotenv!("DATABASE_URL");
-pub(crate) const DEFAULT_MODEL: &str = dotenv!("DEFAULT_MODEL");
-pub(crate) const ALLOWED_MODELS: &str = dotenv!("ALLOWED_MODELS");
-pub(crate) const COMPLETIONS_URL: &str = dotenv!("COMPLETIONS_URL");
-
 #[derive(OpenApi)]
 #[openapi(
     paths(
         routes::legacy::echo,
         metrics::index::index,
+        metrics::usage::usage,
+        metrics::health::readyz,
+        metrics::health::ping,
+        metrics::health::status,
+        metrics::stats::stats,
+        metrics::prometheus::prometheus,
         routes::legacy::get_model,
         routes::legacy::manual_hello,
         routes::completions::completions,
+        routes::batch::batch_completions,
+        routes::admin::set_models,
+        routes::admin::set_maintenance,
+        routes::admin::export_logs,
+        routes::admin::replay_log,
+        routes::admin::debug_config,
     ),
     tags(
         (name = "Chat", description = "Chat completion endpoints"),
         (name = "Legacy", description = "Legacy endpoints"),
-        (name = "Metrics", description = "Metrics and monitoring")
+        (name = "Metrics", description = "Metrics and monitoring"),
+        (name = "Admin", description = "Runtime administration, requires the service key")
     ),
     info(
         title = "Hack Club AI Service",
@@ -61,53 +85,118 @@ pub(crate) const COMPLETIONS_URL: &str = dotenv!("COMPLETIONS_URL");
 )]
 struct ApiDoc;
 
-static CLIENT: LazyLock<Client> = LazyLock::new(|| {
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/json"),
-    );
-    headers.insert(
-        header::USER_AGENT,
-        HeaderValue::from_static("hackclub-ai-proxy/1.0"),
-    );
-
-    let bearer = format!("Bearer {}", KEY);
-    headers.insert(
-        header::AUTHORIZATION,
-        HeaderValue::from_str(&bearer).expect("Invalid authorization header"),
-    );
-
-    Client::builder()
-        .default_headers(headers)
-        .build()
-        .expect("Failed to build HTTP client")
-});
-
-static ALLOWED_MODELS_SET: LazyLock<HashSet<String>> = LazyLock::new(|| {
-    ALLOWED_MODELS
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .collect()
-});
-
-pub(crate) fn is_allowed_model(model: &str) -> bool {
-    ALLOWED_MODELS_SET.contains(model)
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    fmt::init();
+    fmt::fmt().with_span_events(FmtSpan::CLOSE).init();
+
+    // A missing .env is fine: the variables may come from the environment.
+    let _ = dotenvy::dotenv();
+    let config = Arc::new(Config::from_env()?);
+    let connections = Arc::new(ConnectionStats::default());
+    let client = build_client(&config, connections.clone())?;
+
+    if !config.is_configured() {
+        warn!("KEY is not set, completions will return 503 until it is configured");
+    } else if config.startup_probe {
+        match probe_upstream(&client, &config).await {
+            Ok(()) => info!("Upstream probe succeeded"),
+            Err(e) => warn!("Upstream probe failed, check KEY and COMPLETIONS_URL: {e}"),
+        }
+    }
+
+    let state = MetricsState::init(config.clone(), client, connections).await;
 
-    LazyLock::force(&CLIENT);
+    run_migrations(&state).await;
+    spawn_retention(&state);
+
+    let listener = TcpListener::bind(config.listen_addr).await?;
+
+    axum::serve(
+        listener,
+        app(state).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
 
-    let chat_router = Router::new()
-        .route("/chat/completions", post(completions))
-        .layer(middleware::from_fn(validate_model));
+fn app(state: MetricsState) -> Router {
+    // Signatures cover the body as sent, so they are checked before the
+    // compat routes translate it.
+    let signed = || middleware::from_fn_with_state(state.clone(), verify_signature);
+
+    let chat_router = chat_layers(
+        Router::new().route("/chat/completions", post(completions)),
+        &state,
+    )
+    .layer(signed());
+
+    let messages_router = chat_layers(
+        Router::new().route("/v1/messages", post(completions)),
+        &state,
+    )
+    .layer(middleware::from_fn(anthropic_messages))
+    .layer(signed());
+
+    let legacy_completions_router = chat_layers(
+        Router::new().route("/v1/completions", post(completions)),
+        &state,
+    )
+    .layer(middleware::from_fn(legacy_completions))
+    .layer(signed());
+
+    let ws_router = Router::new()
+        .route("/ws/chat/completions", get(ws_completions))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_token_quota,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_geoblock,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_maintenance,
+        ));
+
+    let batch_router = Router::new()
+        .route("/batch/chat/completions", post(batch_completions))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_token_quota,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_geoblock,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_maintenance,
+        ))
+        .layer(signed());
 
     let docs_router = Router::new()
         .route("/docs", get(docs))
-        .route("/openapi.json", get(openapi_axle));
+        .route("/openapi.json", get(openapi_axle))
+        .route("/openapi.yaml", get(openapi_yaml));
+
+    let metrics_router = Router::new()
+        .route("/usage", get(usage))
+        .route("/readyz", get(readyz))
+        .route("/ping", get(ping))
+        .route("/status", get(status))
+        .route("/stats", get(stats))
+        .route("/metrics", get(prometheus));
+
+    let admin_router = Router::new()
+        .route("/admin/models", post(set_models))
+        .route("/admin/maintenance", post(set_maintenance))
+        .route("/admin/logs", get(export_logs))
+        .route("/admin/replay/{log_id}", post(replay_log))
+        .route("/debug/config", get(debug_config))
+        .layer(middleware::from_fn_with_state(state.clone(), require_key))
+        .layer(admin_cors(&state.config));
 
     let legacy_router = Router::new()
         .route("/", get(index))
@@ -121,37 +210,72 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .allow_origin(Any)
         .max_age(Duration::from_secs(60) * 10);
 
-    let state = MetricsState::init().await;
-
-    run_migrations(&state).await;
-    let app = chat_router
+    chat_router
+        .merge(messages_router)
+        .merge(legacy_completions_router)
+        .merge(ws_router)
+        .merge(batch_router)
         .merge(docs_router)
+        .merge(metrics_router)
         .merge(legacy_router)
-        .fallback(|| async {
-            APIError {
-                code: StatusCode::NOT_FOUND,
-                body: Some("Not Found"),
-            }
-        })
+        .fallback(not_found)
         .layer(cors)
-        .with_state(state.clone());
-
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", PORT)).await?;
+        .merge(admin_router)
+        .layer(middleware::from_fn_with_state(state.clone(), access_log))
+        .with_state(state)
+}
 
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await?;
+/// Admin routes only answer cross-origin requests from ADMIN_CORS_ORIGIN, and
+/// from nowhere when it is unset.
+fn admin_cors(config: &Config) -> CorsLayer {
+    match HeaderValue::from_str(&config.admin_cors_origin) {
+        Ok(origin) if !origin.is_empty() => CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers(
+                [header::AUTHORIZATION, header::CONTENT_TYPE]
+                    .into_iter()
+                    .chain(config.auth_header_name.clone())
+                    .collect::<Vec<_>>(),
+            )
+            .max_age(Duration::from_secs(60) * 10),
+        _ => CorsLayer::new(),
+    }
+}
 
-    Ok(())
+fn chat_layers(router: Router<MetricsState>, state: &MetricsState) -> Router<MetricsState> {
+    router
+        .layer(middleware::map_response_with_state(
+            state.clone(),
+            service_notice,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_rate_limit,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            validate_model,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_token_quota,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_geoblock,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_maintenance,
+        ))
 }
 
 async fn run_migrations(state: &metrics::database::MetricsState) {
     if let Some(pool) = &state.db {
         if let Ok(client) = pool.get().await {
             let _ = client
-                .execute(
+                .batch_execute(
                     "CREATE TABLE IF NOT EXISTS api_logs (
                     id SERIAL PRIMARY KEY,
                     request JSONB NOT NULL,
@@ -159,10 +283,225 @@ async fn run_migrations(state: &metrics::database::MetricsState) {
                     ip INET NOT NULL,
                     tokens INTEGER,
                     created_at TIMESTAMPTZ DEFAULT NOW()
-                )",
-                    &[],
+                );
+                ALTER TABLE api_logs
+                    ADD COLUMN IF NOT EXISTS req_bytes INTEGER,
+                    ADD COLUMN IF NOT EXISTS resp_bytes INTEGER,
+                    ADD COLUMN IF NOT EXISTS requested_model TEXT,
+                    ADD COLUMN IF NOT EXISTS resolved_model TEXT,
+                    ADD COLUMN IF NOT EXISTS used_logprobs BOOLEAN NOT NULL DEFAULT FALSE,
+                    ADD COLUMN IF NOT EXISTS token_source TEXT,
+                    ADD COLUMN IF NOT EXISTS method TEXT,
+                    ADD COLUMN IF NOT EXISTS path TEXT,
+                    ADD COLUMN IF NOT EXISTS model_mismatch BOOLEAN NOT NULL DEFAULT FALSE,
+                    ADD COLUMN IF NOT EXISTS seed BIGINT,
+                    ADD COLUMN IF NOT EXISTS refused BOOLEAN NOT NULL DEFAULT FALSE,
+                    ADD COLUMN IF NOT EXISTS latency_ms INTEGER,
+                    ADD COLUMN IF NOT EXISTS user_tag TEXT,
+                    ADD COLUMN IF NOT EXISTS finish_reason TEXT,
+                    ADD COLUMN IF NOT EXISTS completed BOOLEAN NOT NULL DEFAULT TRUE,
+                    ADD COLUMN IF NOT EXISTS reasoning_effort TEXT,
+                    ADD COLUMN IF NOT EXISTS metadata JSONB;
+                CREATE TABLE IF NOT EXISTS usage_daily (
+                    day DATE NOT NULL,
+                    ip INET NOT NULL,
+                    requests BIGINT NOT NULL DEFAULT 0,
+                    tokens BIGINT NOT NULL DEFAULT 0,
+                    PRIMARY KEY (day, ip)
+                );
+                INSERT INTO usage_daily (day, ip, requests, tokens)
+                    SELECT (created_at AT TIME ZONE 'UTC')::date, ip, COUNT(*), COALESCE(SUM(tokens), 0)
+                    FROM api_logs
+                    WHERE created_at IS NOT NULL AND NOT EXISTS (SELECT 1 FROM usage_daily)
+                    GROUP BY 1, 2;",
                 )
                 .await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{
+        Json,
+        http::{Method, StatusCode, header},
+        response::IntoResponse,
+    };
+    use serde_json::{Value, json};
+
+    use super::run_migrations;
+    use crate::test_support::{
+        BROKEN_MODEL, Upstream, chunk, completion, db_state, event_stream, logged_rows, ping,
+        post_json, proxy, state,
+    };
+
+    #[tokio::test]
+    async fn relays_a_completion_from_the_upstream() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let body = json!({ "messages": ping() });
+
+        let response = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["X-Tokens-Used"], "4");
+
+        let completion: Value = response.json().await.unwrap();
+        assert_eq!(completion["model"], "qwen/qwen3-32b");
+        assert_eq!(completion["choices"][0]["message"]["content"], "pong");
+
+        let sent = upstream.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body["model"], "qwen/qwen3-32b");
+        assert_eq!(sent[0].headers["authorization"], "Bearer key");
+    }
+
+    #[tokio::test]
+    async fn surfaces_upstream_errors() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let body = json!({ "model": BROKEN_MODEL, "messages": ping() });
+
+        let response = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    async fn preflight(proxy: SocketAddr, path: &str, origin: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(Method::OPTIONS, format!("http://{proxy}{path}"))
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_refuse_cross_origin_preflights_by_default() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let admin = preflight(proxy, "/admin/models", "https://example.com").await;
+        assert!(
+            admin
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_none()
+        );
+
+        let public = preflight(proxy, "/chat/completions", "https://example.com").await;
+        assert_eq!(public.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[tokio::test]
+    async fn admin_cors_only_allows_the_configured_origin() {
+        let upstream = Upstream::start().await;
+        let overrides = [("ADMIN_CORS_ORIGIN", "https://admin.hackclub.com")];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let allowed = preflight(proxy, "/admin/models", "https://admin.hackclub.com").await;
+        assert_eq!(
+            allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://admin.hackclub.com"
+        );
+        let methods = allowed.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("POST"), "{methods}");
+
+        let other = preflight(proxy, "/admin/models", "https://evil.example").await;
+        let origin = other.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+        assert!(origin.is_none_or(|o| o != "https://evil.example"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn migrations_backfill_the_usage_rollup() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        client
+            .batch_execute(
+                "INSERT INTO api_logs (request, response, ip, tokens)
+                VALUES ('{}', '{}', '10.0.0.2', 5), ('{}', '{}', '10.0.0.2', 7);",
+            )
+            .await
+            .unwrap();
+
+        run_migrations(&state).await;
+
+        let usage = state.usage_for_ip("10.0.0.2".parse().unwrap()).await;
+        assert_eq!(usage, (2, 12));
+    }
+
+    #[tokio::test]
+    async fn serves_cleartext_http2() {
+        let upstream = Upstream::responding(|body| async move {
+            if body["stream"] == true {
+                let events = vec![
+                    (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                    (Duration::ZERO, "[DONE]".to_string()),
+                ];
+                return event_stream(events);
+            }
+            Json(completion(&body["model"], "pong")).into_response()
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let url = format!("http://{proxy}/chat/completions");
+
+        let response = client
+            .post(&url)
+            .json(&json!({ "messages": ping() }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "pong");
+
+        let response = client
+            .post(&url)
+            .json(&json!({ "messages": ping(), "stream": true }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let text = response.text().await.unwrap();
+        assert!(
+            text.contains("pong") && text.contains("data: [DONE]"),
+            "{text}"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn logs_the_route_each_request_came_through() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        logged_rows(&state, 1).await;
+        post_json(proxy, "/v1/completions", json!({ "prompt": "ping" })).await;
+
+        let rows = logged_rows(&state, 2).await;
+        let routes: Vec<(String, String)> = rows
+            .iter()
+            .map(|row| (row.get("method"), row.get("path")))
+            .collect();
+        assert_eq!(
+            routes,
+            [
+                ("POST".to_string(), "/chat/completions".to_string()),
+                ("POST".to_string(), "/v1/completions".to_string()),
+            ]
+        );
+    }
+}
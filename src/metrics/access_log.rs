@@ -0,0 +1,162 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info, warn};
+
+use crate::{delegates::client_ip::ClientIp, metrics::database::MetricsState};
+
+#[derive(Clone, Copy)]
+pub struct TokensUsed(pub i32);
+
+#[derive(Clone)]
+pub struct ServedModel(pub String);
+
+pub async fn access_log(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let start = Instant::now();
+
+    let response = next.run(req).await;
+
+    let elapsed = start.elapsed();
+    let tokens = response.extensions().get::<TokensUsed>().map(|t| t.0);
+    info!(
+        %method,
+        path,
+        status = response.status().as_u16(),
+        ip = %ip,
+        duration_ms = elapsed.as_millis() as u64,
+        tokens,
+        "request"
+    );
+
+    if state
+        .config
+        .slow_request
+        .is_some_and(|threshold| elapsed >= threshold)
+    {
+        let model = response.extensions().get::<ServedModel>().map(|m| &m.0);
+        warn!(
+            %method,
+            path,
+            model,
+            duration_ms = elapsed.as_millis() as u64,
+            "slow request"
+        );
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{Json, response::IntoResponse};
+    use serde_json::json;
+    use tokio::time::sleep;
+
+    use crate::test_support::{
+        BROKEN_MODEL, Upstream, capture_logs, completion, ping, post_json, proxy, state,
+    };
+
+    #[tokio::test]
+    async fn logs_one_line_per_request() {
+        let logs = capture_logs();
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        reqwest::get(format!("http://{proxy}/nope")).await.unwrap();
+
+        let completion = logs.lines_with(&["access_log: request", r#"path="/chat/completions""#]);
+        assert_eq!(completion.len(), 1);
+        for field in [
+            "method=POST",
+            "status=200",
+            "ip=127.0.0.1",
+            "duration_ms=",
+            "tokens=4",
+        ] {
+            assert!(
+                completion[0].contains(field),
+                "{field} missing: {}",
+                completion[0]
+            );
+        }
+
+        let missing = logs.lines_with(&["access_log: request", r#"path="/nope""#]);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].contains("status=404"), "{}", missing[0]);
+        assert!(!missing[0].contains("tokens="), "{}", missing[0]);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_log_a_warning() {
+        let logs = capture_logs();
+        let upstream = Upstream::responding(|body| async move {
+            sleep(Duration::from_millis(100)).await;
+            Json(completion(&body["model"], "pong")).into_response()
+        })
+        .await;
+        let body = json!({ "messages": ping() });
+
+        let unset = proxy(state(&upstream, &[]).await).await;
+        post_json(unset, "/chat/completions", body.clone()).await;
+        assert!(logs.lines_with(&["slow request"]).is_empty());
+
+        let proxy = proxy(state(&upstream, &[("SLOW_REQUEST_MS", "50")]).await).await;
+        post_json(proxy, "/chat/completions", body).await;
+        reqwest::get(format!("http://{proxy}/model")).await.unwrap();
+
+        let slow = logs.lines_with(&["WARN", "slow request"]);
+        assert_eq!(slow.len(), 1, "{slow:?}");
+        for field in [
+            "method=POST",
+            r#"path="/chat/completions""#,
+            "model=\"qwen/qwen3-32b\"",
+            "duration_ms=",
+        ] {
+            assert!(slow[0].contains(field), "{field} missing: {}", slow[0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_request_warnings_name_the_model_that_answered() {
+        let logs = capture_logs();
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("SLOW_REQUEST_MS", "0"),
+            (
+                "MODEL_FALLBACKS",
+                r#"{"openai/gpt-oss-20b":["qwen/qwen3-32b"]}"#,
+            ),
+        ];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let body = json!({ "model": BROKEN_MODEL, "messages": ping() });
+        post_json(proxy, "/chat/completions", body).await;
+        reqwest::get(format!("http://{proxy}/model")).await.unwrap();
+
+        let completion = logs.lines_with(&["slow request", r#"path="/chat/completions""#]);
+        assert_eq!(completion.len(), 1, "{completion:?}");
+        assert!(
+            completion[0].contains(r#"model="qwen/qwen3-32b""#),
+            "{}",
+            completion[0]
+        );
+
+        let models = logs.lines_with(&["slow request", r#"path="/model""#]);
+        assert_eq!(models.len(), 1, "{models:?}");
+        assert!(!models[0].contains("model="), "{}", models[0]);
+    }
+}
@@ -0,0 +1,109 @@
+use deadpool_postgres::Pool;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+use crate::metrics::database::MetricsState;
+
+/// Deletes logs older than LOG_RETENTION_DAYS every
+/// LOG_RETENTION_INTERVAL_SECS. Nothing runs without a database or with a
+/// retention of 0 days. Token totals come from `usage_daily`, which is never
+/// purged, so they survive the cleanup.
+pub fn spawn_retention(state: &MetricsState) {
+    let days = state.config.log_retention_days;
+    let interval = state.config.log_retention_interval;
+    let Some(pool) = state.db.clone() else {
+        return;
+    };
+    if days == 0 || interval.is_zero() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        loop {
+            match purge_logs(&pool, days).await {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {purged} logs older than {days} days"),
+                Err(e) => error!("Failed to purge old logs: {e}"),
+            }
+            sleep(interval).await;
+        }
+    });
+}
+
+pub async fn purge_logs(pool: &Pool, days: u32) -> Result<u64, String> {
+    let client = pool
+        .get()
+        .await
+        .map_err(|e| format!("failed to get database connection from pool: {e}"))?;
+
+    client
+        .execute(
+            "DELETE FROM api_logs WHERE created_at < NOW() - make_interval(days => $1)",
+            &[&(days as i32)],
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::{Upstream, db_state, log_completion, logged_rows, proxy};
+
+    async fn backdate_first_log(state: &MetricsState, days: i32) {
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        client
+            .execute(
+                "UPDATE api_logs SET created_at = NOW() - make_interval(days => $1)
+                WHERE id = (SELECT MIN(id) FROM api_logs)",
+                &[&days],
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn purges_old_logs_and_keeps_recent_ones_and_the_token_total() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let ip = "10.0.0.1".parse().unwrap();
+        log_completion(&state, ip, 1200);
+        logged_rows(&state, 1).await;
+        log_completion(&state, ip, 34);
+        logged_rows(&state, 2).await;
+        backdate_first_log(&state, 40).await;
+
+        assert_eq!(purge_logs(state.db.as_ref().unwrap(), 30).await, Ok(1));
+        let kept = logged_rows(&state, 1).await;
+        assert_eq!(kept[0].get::<_, Option<i32>>("tokens"), Some(34));
+
+        let index = reqwest::get(format!("http://{}/", proxy(state).await))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(index.contains("<b>1234</b> tokens processed"), "{index}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn zero_day_retention_never_purges() {
+        let upstream = Upstream::start().await;
+        let settings = [
+            ("LOG_RETENTION_DAYS", "0"),
+            ("LOG_RETENTION_INTERVAL_SECS", "1"),
+        ];
+        let state = db_state(&upstream, &settings).await;
+        log_completion(&state, "10.0.0.1".parse().unwrap(), 1200);
+        logged_rows(&state, 1).await;
+        backdate_first_log(&state, 400).await;
+
+        spawn_retention(&state);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        logged_rows(&state, 1).await;
+    }
+}
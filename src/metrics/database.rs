@@ -1,88 +1,846 @@
+use std::collections::HashSet;
 use std::net::IpAddr;
 use std::sync::{
-    Arc,
-    atomic::{AtomicI64, Ordering},
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicI64, Ordering},
 };
+use std::time::Duration;
 
-use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod, Runtime::Tokio1};
-use serde_json::Value;
-use tokio_postgres::NoTls;
-use tracing::error;
+use aho_corasick::AhoCorasick;
+use arc_swap::ArcSwap;
+use deadpool_postgres::{
+    Config as PoolConfig, ManagerConfig, Pool, RecyclingMethod, Runtime::Tokio1,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use reqwest::Client;
+use serde_json::{Value, json};
+use tokio::{sync::Semaphore, time::sleep};
+use tokio_postgres::{NoTls, RowStream, types::ToSql};
+use tracing::{debug, error, field::Empty, info_span, warn};
+
+use crate::{
+    config::Config,
+    delegates::{
+        cache::{self, Cache},
+        circuit::CircuitBreaker,
+        timing::timed,
+        upstream::ConnectionStats,
+    },
+    metrics::{gauge::Gauge, webhook},
+    routes::{
+        blocklist,
+        geo::GeoBlocker,
+        limits::RateLimiter,
+        transform::{self, RequestTransform},
+    },
+};
+
+const USAGE_UPSERT: &str = "INSERT INTO usage_daily (day, ip, requests, tokens)
+    VALUES ((NOW() AT TIME ZONE 'UTC')::date, $1, 1, COALESCE($2, 0))
+    ON CONFLICT (day, ip) DO UPDATE SET
+        requests = usage_daily.requests + 1,
+        tokens = usage_daily.tokens + EXCLUDED.tokens";
+
+const LOG_INSERT: &str = "WITH usage AS (
+        INSERT INTO usage_daily (day, ip, requests, tokens)
+        VALUES ((NOW() AT TIME ZONE 'UTC')::date, $3, 1, COALESCE($4, 0))
+        ON CONFLICT (day, ip) DO UPDATE SET
+            requests = usage_daily.requests + 1,
+            tokens = usage_daily.tokens + EXCLUDED.tokens
+    )
+    INSERT INTO api_logs (request, response, ip, tokens, req_bytes, resp_bytes, requested_model, resolved_model, used_logprobs, token_source, method, path, model_mismatch, seed, refused, latency_ms, user_tag, finish_reason, completed, reasoning_effort, metadata)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)";
+
+const DB_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const DB_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+pub struct LogEntry<'a> {
+    pub request: &'a Value,
+    pub response: &'a Value,
+    pub ip: IpAddr,
+    pub tokens: Option<i32>,
+    pub token_source: Option<TokenSource>,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub req_bytes: usize,
+    pub resp_bytes: usize,
+    pub requested_model: Option<&'a str>,
+    pub resolved_model: &'a str,
+    /// From sending the upstream request to the last byte of its response.
+    pub latency: Duration,
+    pub finish_reason: Option<&'a str>,
+    /// False when a stream ended before the upstream's `[DONE]`.
+    pub completed: bool,
+}
+
+/// The model allowlist in its configured order, with a set for lookups.
+#[derive(Default)]
+pub struct AllowedModels {
+    order: Vec<String>,
+    set: HashSet<String>,
+}
+
+impl FromIterator<String> for AllowedModels {
+    fn from_iter<I: IntoIterator<Item = String>>(models: I) -> Self {
+        let mut allowed = Self::default();
+        for model in models {
+            if allowed.set.insert(model.clone()) {
+                allowed.order.push(model);
+            }
+        }
+        allowed
+    }
+}
 
-use crate::DATABASE_URL;
+impl AllowedModels {
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
 
 #[derive(Clone)]
 pub struct MetricsState {
+    pub config: Arc<Config>,
+    pub client: Client,
+    /// Counts behind the connection reuse ratio, shared with the connector
+    /// `client` was built with.
+    pub connections: Arc<ConnectionStats>,
+    pub allowed_models: Arc<ArcSwap<AllowedModels>>,
     pub db: Option<Pool>,
     pub tokens: Arc<AtomicI64>,
+    pub upstream_permits: Arc<Semaphore>,
+    pub max_upstream: usize,
+    pub queue_depth: Arc<Gauge>,
+    pub active_streams: Arc<Gauge>,
+    pub circuit: Arc<CircuitBreaker>,
+    pub cache: Option<Arc<dyn Cache>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub ping_limiter: Arc<RateLimiter>,
+    pub geo: Option<Arc<GeoBlocker>>,
+    pub blocked_phrases: Option<AhoCorasick>,
+    pub maintenance: Arc<AtomicBool>,
+    pub transforms: Arc<[Box<dyn RequestTransform>]>,
+    /// Picks which requests LOG_SAMPLE_RATE keeps, seedable for tests.
+    pub log_rng: Arc<Mutex<StdRng>>,
 }
 
 impl MetricsState {
-    pub async fn init() -> Self {
-        let mut cfg = Config::new();
-        cfg.url = Some(DATABASE_URL.to_string());
-        cfg.manager = Some(ManagerConfig {
-            recycling_method: RecyclingMethod::Fast,
-        });
+    pub async fn init(
+        config: Arc<Config>,
+        client: Client,
+        connections: Arc<ConnectionStats>,
+    ) -> Self {
+        let db = create_pool(&config.database_url);
+        if db.is_none() && config.daily_token_quota > 0 {
+            warn!("DAILY_TOKEN_QUOTA is set but there is no database, so it is not enforced");
+        }
+        let tokens = historical_tokens(db.as_ref()).await;
 
-        match cfg.create_pool(Some(Tokio1), NoTls) {
-            Ok(pool) => Self {
-                db: Some(pool),
-                tokens: std::sync::Arc::new(AtomicI64::new(0)),
-            },
-            Err(e) => {
-                error!("Failed to create database pool: {}", e);
-                Self {
-                    db: None,
-                    tokens: std::sync::Arc::new(AtomicI64::new(0)),
-                }
-            }
+        let max_upstream = match config.max_concurrent_upstream {
+            0 => Semaphore::MAX_PERMITS,
+            n => n,
+        };
+
+        let circuit = CircuitBreaker::new(
+            config.circuit_failure_threshold,
+            config.circuit_window,
+            config.circuit_cooldown,
+        );
+
+        let cache = cache::from_config(&config).await;
+        let geo = GeoBlocker::from_config(&config).map(Arc::new);
+        let blocked_phrases = blocklist::from_config(&config);
+        let allowed_models: AllowedModels = config.allowed_models.iter().cloned().collect();
+
+        Self {
+            allowed_models: Arc::new(ArcSwap::from_pointee(allowed_models)),
+            config,
+            client,
+            connections,
+            db,
+            tokens: Arc::new(AtomicI64::new(tokens)),
+            upstream_permits: Arc::new(Semaphore::new(max_upstream)),
+            max_upstream,
+            queue_depth: Arc::new(Gauge::default()),
+            active_streams: Arc::new(Gauge::default()),
+            circuit: Arc::new(circuit),
+            cache,
+            rate_limiter: Arc::new(RateLimiter::default()),
+            ping_limiter: Arc::new(RateLimiter::default()),
+            geo,
+            blocked_phrases,
+            maintenance: Arc::new(AtomicBool::new(false)),
+            transforms: transform::pipeline().into(),
+            log_rng: Arc::new(Mutex::new(StdRng::from_os_rng())),
         }
     }
 
+    pub fn is_allowed_model(&self, model: &str) -> bool {
+        self.allowed_models.load().set.contains(model)
+    }
+
+    /// The allowlist in the order it was configured.
+    pub fn allowed_models(&self) -> Vec<String> {
+        self.allowed_models.load().order.clone()
+    }
+
+    /// Sends a completion upstream, counting it toward the connection reuse
+    /// ratio.
+    pub async fn send_upstream(
+        &self,
+        request: reqwest::Request,
+    ) -> reqwest::Result<reqwest::Response> {
+        self.connections.count_request();
+        self.client.execute(request).await
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.max_upstream - self.upstream_permits.available_permits()
+    }
+
     #[inline]
     pub fn inc_tokens(&self, n: i64) {
         self.tokens.fetch_add(n, Ordering::Relaxed);
     }
 
-    pub async fn log_request(
-        &self,
-        request: &Value,
-        response: &Value,
-        ip: IpAddr,
-        tokens: Option<i32>,
-    ) {
-        if let Some(pool) = &self.db {
-            match pool.get().await {
-                Ok(client) => {
-                    if let Err(e) = client
-                        .execute(
-                            "INSERT INTO api_logs (request, response, ip, tokens) VALUES ($1, $2, $3, $4)",
-                            &[request, response, &ip, &tokens],
-                        )
-                        .await
-                    {
-                        error!("Failed to log request: {}", e);
-                    }
+    /// Whether a request gets an `api_logs` row. Errors always do, the rest
+    /// with probability LOG_SAMPLE_RATE.
+    fn sampled(&self, is_error: bool) -> bool {
+        is_error || self.log_rng.lock().unwrap().random::<f64>() < self.config.log_sample_rate
+    }
+
+    pub fn log_request(&self, entry: LogEntry<'_>) {
+        if let Some(token_count) = entry.tokens {
+            self.inc_tokens(token_count as i64);
+        }
 
-                    if let Some(token_count) = tokens {
-                        self.inc_tokens(token_count as i64);
+        // Sampled-out requests skip `api_logs` but still count in
+        // `usage_daily`, so the token quota, /usage and the index total stay
+        // exact.
+        let is_error = entry.response.get("error").is_some();
+        let sampled = self.sampled(is_error);
+
+        let used_logprobs = entry.request.get("logprobs").and_then(Value::as_bool) == Some(true);
+        let seed = entry.request.get("seed").and_then(Value::as_i64);
+        let user_tag = entry.request.get("user").and_then(Value::as_str);
+        let reasoning_effort = entry
+            .request
+            .get("reasoning_effort")
+            .and_then(Value::as_str);
+        let metadata = entry.request.get("metadata").filter(|m| !m.is_null());
+        let refused = is_refusal(entry.response);
+        let latency_ms = i32::try_from(entry.latency.as_millis()).unwrap_or(i32::MAX);
+        let model_mismatch = match entry.response.get("model").and_then(Value::as_str) {
+            Some(answered_by) if answered_by != entry.resolved_model => {
+                warn!(
+                    "Upstream answered with {} instead of {}",
+                    answered_by, entry.resolved_model
+                );
+                true
+            }
+            _ => false,
+        };
+
+        let req_bytes = byte_count(entry.req_bytes);
+        let resp_bytes = byte_count(entry.resp_bytes);
+        let token_source = entry.token_source.map(TokenSource::as_str);
+
+        if sampled && let Some(url) = &self.config.log_webhook_url {
+            let record = json!({
+                "request": entry.request,
+                "response": entry.response,
+                "ip": entry.ip.to_string(),
+                "tokens": entry.tokens,
+                "req_bytes": req_bytes,
+                "resp_bytes": resp_bytes,
+                "requested_model": entry.requested_model,
+                "resolved_model": entry.resolved_model,
+                "used_logprobs": used_logprobs,
+                "token_source": token_source,
+                "method": entry.method,
+                "path": entry.path,
+                "model_mismatch": model_mismatch,
+                "seed": seed,
+                "refused": refused,
+                "latency_ms": latency_ms,
+                "user_tag": user_tag,
+                "finish_reason": entry.finish_reason,
+                "completed": entry.completed,
+                "reasoning_effort": reasoning_effort,
+                "metadata": metadata,
+            });
+            webhook::mirror(&self.client, url, record);
+        }
+
+        let Some(pool) = self.db.clone() else {
+            return;
+        };
+
+        // The insert runs in the background so retries never hold up the
+        // response, which means every borrowed column is copied out first.
+        let request = entry.request.clone();
+        let response = entry.response.clone();
+        let ip = entry.ip;
+        let tokens = entry.tokens;
+        let requested_model = entry.requested_model.map(str::to_owned);
+        let resolved_model = entry.resolved_model.to_owned();
+        let method = entry.method.to_owned();
+        let path = entry.path.to_owned();
+        let user_tag = user_tag.map(str::to_owned);
+        let finish_reason = entry.finish_reason.map(str::to_owned);
+        let completed = entry.completed;
+        let reasoning_effort = reasoning_effort.map(str::to_owned);
+        let metadata = metadata.cloned();
+        let attempts = self.config.db_write_attempts.max(1);
+
+        tokio::spawn(async move {
+            let logged: [&(dyn ToSql + Sync); 21] = [
+                &request,
+                &response,
+                &ip,
+                &tokens,
+                &req_bytes,
+                &resp_bytes,
+                &requested_model,
+                &resolved_model,
+                &used_logprobs,
+                &token_source,
+                &method,
+                &path,
+                &model_mismatch,
+                &seed,
+                &refused,
+                &latency_ms,
+                &user_tag,
+                &finish_reason,
+                &completed,
+                &reasoning_effort,
+                &metadata,
+            ];
+            let (statement, params): (&str, &[&(dyn ToSql + Sync)]) = if sampled {
+                (LOG_INSERT, &logged)
+            } else {
+                (USAGE_UPSERT, &[&ip, &tokens])
+            };
+
+            let span = info_span!("db_log", elapsed_ms = Empty);
+            timed(span, async {
+                for attempt in 1..=attempts {
+                    let result = match pool.get().await {
+                        Ok(client) => client
+                            .execute(statement, params)
+                            .await
+                            .map(drop)
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(format!("failed to get database connection from pool: {e}")),
+                    };
+
+                    match result {
+                        Ok(()) => return,
+                        Err(e) if attempt == attempts => {
+                            error!("Failed to log request after {attempts} attempts: {e}");
+                        }
+                        Err(e) => {
+                            debug!("Logging attempt {attempt} failed, retrying: {e}");
+                            sleep(retry_backoff(attempt)).await;
+                        }
                     }
                 }
-                Err(e) => {
-                    error!("Failed to get database connection from pool: {}", e);
-                }
+            })
+            .await;
+        });
+    }
+
+    /// Requests and tokens for `ip` since UTC midnight, from the per-day
+    /// rollup rather than a scan of `api_logs`. Zeros without a database,
+    /// which leaves DAILY_TOKEN_QUOTA unenforced.
+    pub async fn usage_for_ip(&self, ip: IpAddr) -> (i64, i64) {
+        let Some(pool) = &self.db else {
+            return (0, 0);
+        };
+
+        let client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to get database connection from pool: {}", e);
+                return (0, 0);
+            }
+        };
+
+        match client
+            .query_opt(
+                "SELECT requests, tokens FROM usage_daily
+                WHERE day = (NOW() AT TIME ZONE 'UTC')::date AND ip = $1",
+                &[&ip],
+            )
+            .await
+        {
+            Ok(row) => row.map_or((0, 0), |row| (row.get("requests"), row.get("tokens"))),
+            Err(e) => {
+                error!("Failed to query usage: {}", e);
+                (0, 0)
+            }
+        }
+    }
+
+    pub async fn top_models(&self, limit: i64) -> Option<Vec<(String, i64)>> {
+        let client = self.db.as_ref()?.get().await.ok()?;
+
+        match client
+            .query(
+                "SELECT resolved_model AS model, COALESCE(SUM(tokens), 0) AS tokens FROM api_logs
+                WHERE resolved_model IS NOT NULL AND created_at >= NOW() - INTERVAL '30 days'
+                GROUP BY resolved_model ORDER BY tokens DESC LIMIT $1",
+                &[&limit],
+            )
+            .await
+        {
+            Ok(rows) => Some(
+                rows.iter()
+                    .map(|row| (row.get("model"), row.get("tokens")))
+                    .collect(),
+            ),
+            Err(e) => {
+                error!("Failed to query top models: {}", e);
+                None
+            }
+        }
+    }
+
+    /// p50, p95 and p99 upstream latency in milliseconds over the last day.
+    pub async fn latency_percentiles(&self) -> Option<Vec<f64>> {
+        let client = self.db.as_ref()?.get().await.ok()?;
+
+        match client
+            .query_one(
+                "SELECT percentile_cont(ARRAY[0.5, 0.95, 0.99]) WITHIN GROUP (ORDER BY latency_ms) AS latencies
+                FROM api_logs WHERE latency_ms IS NOT NULL AND created_at >= NOW() - INTERVAL '24 hours'",
+                &[],
+            )
+            .await
+        {
+            Ok(row) => row.get("latencies"),
+            Err(e) => {
+                error!("Failed to query latency percentiles: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn logged_request(&self, id: i32) -> Option<Value> {
+        let client = self.db.as_ref()?.get().await.ok()?;
+
+        match client
+            .query_opt(
+                "SELECT request FROM api_logs WHERE id = $1 AND request <> 'null'::jsonb",
+                &[&id],
+            )
+            .await
+        {
+            Ok(row) => row.map(|row| row.get("request")),
+            Err(e) => {
+                error!("Failed to load logged request {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    pub async fn recent_logs(&self, limit: i64) -> Option<RowStream> {
+        let client = self.db.as_ref()?.get().await.ok()?;
+
+        match client
+            .query_raw(
+                "SELECT json_build_object(
+                    'id', id, 'created_at', created_at, 'requested_model', requested_model,
+                    'resolved_model', resolved_model, 'tokens', tokens,
+                    'request', request, 'response', response, 'metadata', metadata
+                )::jsonb AS line FROM api_logs ORDER BY created_at DESC LIMIT $1",
+                [&limit as &(dyn ToSql + Sync)],
+            )
+            .await
+        {
+            Ok(rows) => Some(rows),
+            Err(e) => {
+                error!("Failed to query recent logs: {}", e);
+                None
             }
         }
     }
 }
 
-pub fn extract_tokens(response: &Value, is_streaming: bool) -> Option<i32> {
-    let usage = if is_streaming {
-        response.get("x_groq")?.get("usage")?
-    } else {
-        response.get("usage")?
+fn create_pool(url: &str) -> Option<Pool> {
+    if url.is_empty() {
+        warn!("DATABASE_URL is not set, running without request logging");
+        return None;
+    }
+
+    if let Err(e) = url.parse::<tokio_postgres::Config>() {
+        warn!(
+            "DATABASE_URL is invalid, running without request logging: {}",
+            e
+        );
+        return None;
+    }
+
+    let mut cfg = PoolConfig::new();
+    cfg.url = Some(url.to_string());
+    cfg.manager = Some(ManagerConfig {
+        recycling_method: RecyclingMethod::Fast,
+    });
+
+    match cfg.create_pool(Some(Tokio1), NoTls) {
+        Ok(pool) => Some(pool),
+        Err(e) => {
+            error!("Failed to create database pool: {}", e);
+            None
+        }
+    }
+}
+
+/// Total tokens already logged, so the in-memory counter carries over restarts.
+/// Starts from zero when the database is unavailable.
+async fn historical_tokens(db: Option<&Pool>) -> i64 {
+    let Some(pool) = db else {
+        return 0;
+    };
+
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Failed to seed the token counter, starting at zero: {}", e);
+            return 0;
+        }
+    };
+
+    match client
+        .query_one(
+            "SELECT COALESCE(SUM(tokens), 0)::bigint AS sum FROM usage_daily",
+            &[],
+        )
+        .await
+    {
+        Ok(row) => row.get("sum"),
+        Err(e) => {
+            warn!("Failed to seed the token counter, starting at zero: {}", e);
+            0
+        }
+    }
+}
+
+/// Doubles from DB_RETRY_BACKOFF after each failed attempt, up to
+/// DB_RETRY_MAX_BACKOFF however large DB_WRITE_ATTEMPTS is.
+fn retry_backoff(attempt: u32) -> Duration {
+    DB_RETRY_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(DB_RETRY_MAX_BACKOFF)
+}
+
+fn byte_count(n: usize) -> i32 {
+    i32::try_from(n).unwrap_or(i32::MAX)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    Usage,
+    GroqUsage,
+    Estimated,
+}
+
+impl TokenSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenSource::Usage => "usage",
+            TokenSource::GroqUsage => "x_groq.usage",
+            TokenSource::Estimated => "estimated",
+        }
+    }
+}
+
+pub fn extract_tokens(response: &Value, estimate: bool) -> Option<(i32, TokenSource)> {
+    if let Some(tokens) = response.get("usage").and_then(usage_total) {
+        return Some((tokens, TokenSource::Usage));
+    }
+
+    if let Some(tokens) = response
+        .get("x_groq")
+        .and_then(|x| x.get("usage"))
+        .and_then(usage_total)
+    {
+        return Some((tokens, TokenSource::GroqUsage));
+    }
+
+    if estimate {
+        return estimate_tokens(response).map(|tokens| (tokens, TokenSource::Estimated));
+    }
+
+    None
+}
+
+const REFUSAL_PREFIXES: [&str; 5] = [
+    "I'm sorry, but I can't",
+    "I'm sorry, but I cannot",
+    "I can't help with",
+    "I can't assist with",
+    "I cannot assist with",
+];
+
+/// True when any choice was cut off by the upstream's content filter, carries
+/// an OpenAI-style `refusal`, or opens with a stock refusal phrase.
+pub fn is_refusal(response: &Value) -> bool {
+    let Some(choices) = response.get("choices").and_then(Value::as_array) else {
+        return false;
+    };
+
+    choices.iter().any(|choice| {
+        let message = choice.get("message").or_else(|| choice.get("delta"));
+        let refusal = message
+            .and_then(|m| m.get("refusal"))
+            .and_then(Value::as_str)
+            .is_some_and(|r| !r.is_empty());
+        let stock_phrase = message
+            .and_then(|m| m.get("content"))
+            .and_then(Value::as_str)
+            .is_some_and(|content| {
+                let content = content.trim_start().replace('\u{2019}', "'");
+                REFUSAL_PREFIXES.iter().any(|p| content.starts_with(p))
+            });
+
+        choice.get("finish_reason").and_then(Value::as_str) == Some("content_filter")
+            || refusal
+            || stock_phrase
+    })
+}
+
+/// The last `finish_reason` any choice reported.
+pub fn finish_reason(response: &Value) -> Option<&str> {
+    response
+        .get("choices")?
+        .as_array()?
+        .iter()
+        .rev()
+        .find_map(|choice| choice.get("finish_reason")?.as_str())
+}
+
+fn usage_total(usage: &Value) -> Option<i32> {
+    if let Some(total) = usage.get("total_tokens").and_then(Value::as_i64) {
+        return Some(total as i32);
+    }
+
+    let count = |keys: [&str; 2]| keys.iter().find_map(|k| usage.get(k)?.as_i64());
+    let prompt = count(["prompt_tokens", "input_tokens"]);
+    let completion = count(["completion_tokens", "output_tokens"]);
+    match (prompt, completion) {
+        (None, None) => None,
+        (prompt, completion) => Some((prompt.unwrap_or(0) + completion.unwrap_or(0)) as i32),
+    }
+}
+
+// Roughly four characters per token, which is close enough for English text.
+fn estimate_tokens(response: &Value) -> Option<i32> {
+    let chars: usize = response
+        .get("choices")?
+        .as_array()?
+        .iter()
+        .filter_map(|choice| choice.get("message").or_else(|| choice.get("delta")))
+        .filter_map(|message| message.get("content")?.as_str())
+        .map(|content| content.chars().count())
+        .sum();
+
+    estimate_from_chars(chars)
+}
+
+pub fn estimate_from_chars(chars: usize) -> Option<i32> {
+    (chars > 0).then(|| chars.div_ceil(4) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        run_migrations,
+        test_support::{
+            Upstream, capture_logs, database, db_state, eventually, log_completion, logged_rows,
+            state,
+        },
     };
 
-    usage.get("total_tokens")?.as_i64().map(|t| t as i32)
+    #[test]
+    fn retry_backoff_doubles_then_caps() {
+        assert_eq!(retry_backoff(1), DB_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(2), DB_RETRY_BACKOFF * 2);
+        assert_eq!(retry_backoff(40), DB_RETRY_MAX_BACKOFF);
+        assert_eq!(retry_backoff(u32::MAX), DB_RETRY_MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn unreachable_databases_are_retried_then_given_up_on() {
+        let logs = capture_logs();
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("DATABASE_URL", "postgresql://postgres@127.0.0.1:9/hackclub"),
+            ("DB_WRITE_ATTEMPTS", "3"),
+        ];
+        let state = state(&upstream, &overrides).await;
+
+        log_completion(&state, "10.0.0.9".parse().unwrap(), 4);
+        eventually(|| async { !logs.lines_with(&["after 3 attempts"]).is_empty() }).await;
+
+        assert_eq!(logs.lines_with(&["Logging attempt", "retrying"]).len(), 2);
+        assert_eq!(
+            logs.lines_with(&["ERROR", "Failed to log request"]).len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn failed_log_writes_succeed_on_a_later_attempt() {
+        let logs = capture_logs();
+        let upstream = Upstream::start().await;
+        let url = database().await;
+        let overrides = [("DATABASE_URL", url.as_str()), ("DB_WRITE_ATTEMPTS", "5")];
+        let state = state(&upstream, &overrides).await;
+
+        // The first insert fails on the missing table, which exists by the retry.
+        log_completion(&state, "10.0.0.10".parse().unwrap(), 4);
+        eventually(|| async { !logs.lines_with(&["Logging attempt 1 failed"]).is_empty() }).await;
+        run_migrations(&state).await;
+
+        logged_rows(&state, 1).await;
+        assert!(logs.lines_with(&["Failed to log request"]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_or_invalid_database_urls_run_without_a_pool() {
+        assert!(create_pool("").is_none());
+        assert!(create_pool("postgres://host:notaport/db").is_none());
+        assert!(create_pool("postgresql://postgres@localhost/hackclub").is_some());
+
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[("DATABASE_URL", "")]).await;
+        assert!(state.db.is_none());
+    }
+
+    #[test]
+    fn reported_usage_wins_over_the_estimate() {
+        let groq = json!({ "x_groq": { "usage": { "prompt_tokens": 2, "completion_tokens": 3 } } });
+        assert_eq!(
+            extract_tokens(&groq, true),
+            Some((5, TokenSource::GroqUsage))
+        );
+
+        let usage = json!({ "usage": { "total_tokens": 9 }, "choices": [] });
+        assert_eq!(extract_tokens(&usage, true), Some((9, TokenSource::Usage)));
+    }
+
+    #[test]
+    fn estimates_only_when_enabled() {
+        let response = json!({
+            "choices": [{ "message": { "role": "assistant", "content": "twelve chars" } }],
+        });
+        assert_eq!(
+            extract_tokens(&response, true),
+            Some((3, TokenSource::Estimated))
+        );
+        assert_eq!(extract_tokens(&response, false), None);
+        assert_eq!(extract_tokens(&json!({ "choices": [] }), true), None);
+    }
+
+    #[tokio::test]
+    async fn sampling_keeps_about_the_configured_fraction() {
+        let upstream = Upstream::start().await;
+        let mut state = state(&upstream, &[("LOG_SAMPLE_RATE", "0.25")]).await;
+        state.log_rng = Arc::new(Mutex::new(StdRng::seed_from_u64(344)));
+
+        let kept = (0..10_000).filter(|_| state.sampled(false)).count();
+        assert!((2_300..=2_700).contains(&kept), "kept {kept} of 10000");
+        assert!((0..100).all(|_| state.sampled(true)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn sampled_out_requests_only_count_toward_usage() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("LOG_SAMPLE_RATE", "0")]).await;
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        log_completion(&state, ip, 5);
+        log_completion(&state, ip, 6);
+        eventually(|| async { state.usage_for_ip(ip).await == (2, 11) }).await;
+
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        let rows = client.query("SELECT id FROM api_logs", &[]).await.unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn log_writes_are_timed_in_their_own_span() {
+        let logs = capture_logs();
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+
+        log_completion(&state, "10.0.0.8".parse().unwrap(), 4);
+        eventually(|| async {
+            !logs
+                .lines_with(&["db_log{", "elapsed_ms=", "close"])
+                .is_empty()
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn token_counter_is_seeded_from_the_database() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        assert_eq!(state.tokens.load(Ordering::Relaxed), 0);
+
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        client
+            .batch_execute(
+                "INSERT INTO usage_daily (day, ip, requests, tokens) VALUES
+                ('2025-01-10', '10.0.0.1', 3, 1200),
+                ('2025-01-10', '10.0.0.2', 1, 34),
+                (CURRENT_DATE, '10.0.0.1', 2, 500);",
+            )
+            .await
+            .unwrap();
+
+        let restarted =
+            MetricsState::init(state.config.clone(), Client::new(), Arc::default()).await;
+        assert_eq!(restarted.tokens.load(Ordering::Relaxed), 1734);
+    }
+
+    #[tokio::test]
+    async fn token_counter_starts_at_zero_without_a_database() {
+        let upstream = Upstream::start().await;
+        let unreachable = [("DATABASE_URL", "postgresql://postgres@127.0.0.1:9/hackclub")];
+        let state = state(&upstream, &unreachable).await;
+        assert!(state.db.is_some());
+        assert_eq!(state.tokens.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn top_models_orders_by_tokens_within_30_days() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        client
+            .batch_execute(
+                "INSERT INTO api_logs (request, response, ip, tokens, resolved_model, created_at) VALUES
+                ('{}', '{}', '10.0.0.1', 10, 'small', NOW()),
+                ('{}', '{}', '10.0.0.1', 50, 'large', NOW()),
+                ('{}', '{}', '10.0.0.1', 30, 'medium', NOW()),
+                ('{}', '{}', '10.0.0.1', 30, 'small', NOW()),
+                ('{}', '{}', '10.0.0.1', 999, 'stale', NOW() - INTERVAL '31 days'),
+                ('{}', '{}', '10.0.0.1', 999, NULL, NOW());",
+            )
+            .await
+            .unwrap();
+
+        let top = state.top_models(2).await.unwrap();
+        assert_eq!(top, [("large".to_string(), 50), ("small".to_string(), 40)]);
+    }
 }
@@ -0,0 +1,59 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Default)]
+pub struct Gauge(AtomicUsize);
+
+impl Gauge {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn track(self: &Arc<Self>) -> GaugeGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(self.clone())
+    }
+}
+
+pub struct GaugeGuard(Arc<Gauge>);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    #[test]
+    fn guards_count_until_dropped() {
+        let gauge = Arc::new(Gauge::default());
+        let first = gauge.track();
+        let second = gauge.track();
+        assert_eq!(gauge.get(), 2);
+
+        drop(first);
+        assert_eq!(gauge.get(), 1);
+        drop(second);
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[test]
+    fn unwinding_releases_the_guard() {
+        let gauge = Arc::new(Gauge::default());
+        let tracked = gauge.clone();
+        let result = panic::catch_unwind(move || {
+            let _guard = tracked.track();
+            panic!("stream failed");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(gauge.get(), 0);
+    }
+}
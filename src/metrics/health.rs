@@ -0,0 +1,216 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::json;
+use tokio::time::timeout;
+use utoipa::ToSchema;
+
+use crate::{
+    delegates::{circuit::CircuitState, client_ip::ClientIp, upstream::probe_upstream},
+    metrics::database::MetricsState,
+    routes::limits::too_many_requests,
+};
+
+const PING_LIMIT: u32 = 10;
+const PING_WINDOW: Duration = Duration::from_secs(60);
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Ok,
+    Degraded,
+    Down,
+}
+
+/// Every subsystem at a glance, for dashboards.
+#[derive(Serialize, ToSchema)]
+pub struct Status {
+    /// `ok` when a pooled connection can be taken, `down` otherwise or
+    /// without DATABASE_URL.
+    db: Health,
+    /// `degraded` while the circuit is not closed or every upstream slot is
+    /// taken.
+    upstream: Health,
+    queue_depth: usize,
+    circuit: CircuitState,
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Service is configured and ready", content_type = "text/plain"),
+        (status = 503, description = "Service is degraded, e.g. KEY is not set", content_type = "text/plain")
+    ),
+    tag = "Metrics"
+)]
+pub async fn readyz(State(state): State<MetricsState>) -> impl IntoResponse {
+    if state.config.is_configured() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready: KEY is not set")
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/status",
+    responses(
+        (status = 200, description = "Database, upstream, queue and circuit breaker state", body = Status,
+            example = json!({ "db": "ok", "upstream": "ok", "queue_depth": 0, "circuit": "closed" }))
+    ),
+    tag = "Metrics"
+)]
+pub async fn status(State(state): State<MetricsState>) -> Json<Status> {
+    let db = match &state.db {
+        Some(pool) => match timeout(DB_CHECK_TIMEOUT, pool.get()).await {
+            Ok(Ok(_)) => Health::Ok,
+            _ => Health::Down,
+        },
+        None => Health::Down,
+    };
+
+    let circuit = state.circuit.state();
+    let upstream = if circuit != CircuitState::Closed || state.in_flight() >= state.max_upstream {
+        Health::Degraded
+    } else {
+        Health::Ok
+    };
+
+    Json(Status {
+        db,
+        upstream,
+        queue_depth: state.queue_depth.get(),
+        circuit,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/ping",
+    responses(
+        (status = 200, description = "Result of a models call against the upstream", body = serde_json::Value,
+            example = json!({ "upstream_ok": true, "latency_ms": 142 })),
+        (status = 429, description = "Pinged too often, at most 10 times a minute per IP")
+    ),
+    tag = "Metrics"
+)]
+pub async fn ping(State(state): State<MetricsState>, ClientIp(ip): ClientIp) -> Response {
+    if let Err(retry_in) = state
+        .ping_limiter
+        .check(ip, "ping", PING_LIMIT, PING_WINDOW)
+    {
+        return too_many_requests(
+            format!("Ping rate limit exceeded, try again in {retry_in} seconds"),
+            retry_in,
+        );
+    }
+
+    let started = Instant::now();
+    let upstream_ok = matches!(
+        timeout(PING_TIMEOUT, probe_upstream(&state.client, &state.config)).await,
+        Ok(Ok(()))
+    );
+
+    Json(json!({
+        "upstream_ok": upstream_ok,
+        "latency_ms": started.elapsed().as_millis() as u64,
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_support::{Upstream, db_state, proxy, state};
+
+    async fn get_json(proxy: SocketAddr, path: &str) -> (StatusCode, Value) {
+        let response = reqwest::get(format!("http://{proxy}{path}")).await.unwrap();
+        let status = response.status();
+        (status, response.json().await.unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn ping_reports_a_reachable_upstream() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let (status, ping) = get_json(proxy, "/ping").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ping["upstream_ok"], true);
+        assert!(ping["latency_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn ping_reports_an_unreachable_upstream() {
+        let upstream = Upstream::start().await;
+        let closed = [("COMPLETIONS_URL", "http://127.0.0.1:9/v1/chat/completions")];
+        let proxy = proxy(state(&upstream, &closed).await).await;
+
+        let (status, ping) = get_json(proxy, "/ping").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ping["upstream_ok"], false);
+    }
+
+    #[tokio::test]
+    async fn ping_is_rate_limited_per_ip() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        for _ in 0..PING_LIMIT {
+            assert_eq!(get_json(proxy, "/ping").await.0, StatusCode::OK);
+        }
+        let response = reqwest::get(format!("http://{proxy}/ping")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn status_summarizes_every_subsystem() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+        assert!(state.db.is_none());
+        let proxy = proxy(state).await;
+
+        let (status, summary) = get_json(proxy, "/status").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            summary,
+            json!({ "db": "down", "upstream": "ok", "queue_depth": 0, "circuit": "closed" })
+        );
+    }
+
+    #[tokio::test]
+    async fn status_degrades_with_an_open_circuit() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[("CIRCUIT_FAILURE_THRESHOLD", "1")]).await;
+        state.circuit.record_failure();
+        let proxy = proxy(state).await;
+
+        let (_, summary) = get_json(proxy, "/status").await;
+        assert_eq!(summary["upstream"], "degraded");
+        assert_eq!(summary["circuit"], "open");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn status_reports_a_reachable_database() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(db_state(&upstream, &[]).await).await;
+
+        let (_, summary) = get_json(proxy, "/status").await;
+        assert_eq!(summary["db"], "ok");
+    }
+}
@@ -4,7 +4,7 @@ use axum::{
 };
 use maud::html;
 
-use crate::{ALLOWED_MODELS, DEFAULT_MODEL, metrics::database::MetricsState};
+use crate::metrics::database::MetricsState;
 
 #[utoipa::path(
     get,
@@ -20,7 +20,10 @@ pub async fn index(State(state): State<MetricsState>) -> impl IntoResponse {
     if let Some(pool) = &state.db {
         if let Ok(client) = pool.get().await {
             if let Ok(rows) = client
-                .query("SELECT COALESCE(SUM(tokens), 0) AS sum FROM api_logs", &[])
+                .query(
+                    "SELECT COALESCE(SUM(tokens), 0)::bigint AS sum FROM usage_daily",
+                    &[],
+                )
                 .await
             {
                 if let Some(row) = rows.first() {
@@ -30,6 +33,8 @@ pub async fn index(State(state): State<MetricsState>) -> impl IntoResponse {
         }
     }
 
+    let top_models = state.top_models(5).await.filter(|m| !m.is_empty());
+
     Html(
         html! {
             html lang="en" {
@@ -51,23 +56,47 @@ pub async fn index(State(state): State<MetricsState>) -> impl IntoResponse {
                         p {
                             b { (total) }
                             " tokens processed since January 2025. Default model: "
-                            b { code { (DEFAULT_MODEL) } }
+                            b { code { (state.config.default_model) } }
                         }
                         p {
                             "Available models: "
                             b {
-                                @for (i, model) in ALLOWED_MODELS.split(',').enumerate() {
+                                @for (i, model) in state.allowed_models().iter().enumerate() {
                                     @if i > 0 { ", " }
-                                    code { (model.trim()) }
+                                    code { (model) }
                                 }
                             }
                         }
+                        @if !state.config.service_notice.is_empty() {
+                            p { b { "Notice: " } (state.config.service_notice) }
+                        }
                         p {
                             "Open source at "
                             a href="https://github.com/hackclub/ai" { "github.com/hackclub/ai" }
                             "!"
                         }
                     }
+                    @if let Some(models) = &top_models {
+                        section {
+                            h2 { "Top Models" }
+                            table {
+                                thead {
+                                    tr {
+                                        th { "Model" }
+                                        th { "Tokens (last 30 days)" }
+                                    }
+                                }
+                                tbody {
+                                    @for (model, tokens) in models {
+                                        tr {
+                                            td { code { (model) } }
+                                            td { (tokens) }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
                     section {
                         h2 { "Usage" }
                         h3 { "Chat Completions" }
@@ -1,2 +1,10 @@
+pub mod access_log;
+pub mod cleanup;
 pub mod database;
+pub mod gauge;
+pub mod health;
 pub mod index;
+pub mod prometheus;
+pub mod stats;
+pub mod usage;
+pub mod webhook;
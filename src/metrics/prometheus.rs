@@ -0,0 +1,158 @@
+use std::{fmt::Write, sync::atomic::Ordering};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, header},
+    response::IntoResponse,
+};
+
+use crate::{delegates::error::ErrorKind, metrics::database::MetricsState};
+
+const OPENMETRICS: &str = "application/openmetrics-text";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Gauges and counters in the Prometheus text format, or in OpenMetrics when the Accept header asks for application/openmetrics-text", content_type = "text/plain")
+    ),
+    tag = "Metrics"
+)]
+pub async fn prometheus(
+    State(state): State<MetricsState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains(OPENMETRICS));
+    // OpenMetrics names a counter family without its `_total` suffix.
+    let family = |name: &'static str| {
+        if openmetrics {
+            name.strip_suffix("_total").unwrap_or(name)
+        } else {
+            name
+        }
+    };
+
+    let samples = [
+        (
+            "hackclub_ai_active_streams",
+            "gauge",
+            "Streaming responses currently open",
+            state.active_streams.get() as i64,
+        ),
+        (
+            "hackclub_ai_queue_depth",
+            "gauge",
+            "Requests waiting for an upstream slot",
+            state.queue_depth.get() as i64,
+        ),
+        (
+            "hackclub_ai_in_flight",
+            "gauge",
+            "Upstream requests currently in flight",
+            state.in_flight() as i64,
+        ),
+        (
+            "hackclub_ai_tokens_total",
+            "counter",
+            "Tokens logged, including the database total at startup",
+            state.tokens.load(Ordering::Relaxed),
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in samples {
+        let _ = writeln!(body, "# HELP {} {help}", family(name));
+        let _ = writeln!(body, "# TYPE {} {kind}", family(name));
+        let _ = writeln!(body, "{name} {value}");
+    }
+
+    let errors = family("hackclub_ai_errors_total");
+    let _ = writeln!(body, "# HELP {errors} Error responses by who was at fault");
+    let _ = writeln!(body, "# TYPE {errors} counter");
+    for kind in ErrorKind::ALL {
+        let _ = writeln!(
+            body,
+            "hackclub_ai_errors_total{{kind=\"{}\"}} {}",
+            kind.as_str(),
+            kind.count()
+        );
+    }
+
+    if openmetrics {
+        body.push_str("# EOF\n");
+        ([(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)], body)
+    } else {
+        ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::test_support::{Upstream, proxy, state};
+
+    async fn scrape(proxy: SocketAddr, accept: &str) -> (String, String) {
+        let response = reqwest::Client::new()
+            .get(format!("http://{proxy}/metrics"))
+            .header(header::ACCEPT, accept)
+            .send()
+            .await
+            .unwrap();
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        (content_type, response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn exposes_every_family_in_the_prometheus_text_format() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+        state.tokens.store(1734, Ordering::Relaxed);
+        let proxy = proxy(state).await;
+
+        let (content_type, body) = scrape(proxy, "text/plain").await;
+        assert_eq!(content_type, "text/plain; version=0.0.4");
+        for line in [
+            "# TYPE hackclub_ai_active_streams gauge",
+            "hackclub_ai_active_streams 0",
+            "# TYPE hackclub_ai_queue_depth gauge",
+            "hackclub_ai_queue_depth 0",
+            "# TYPE hackclub_ai_in_flight gauge",
+            "hackclub_ai_in_flight 0",
+            "# TYPE hackclub_ai_tokens_total counter",
+            "hackclub_ai_tokens_total 1734",
+            "# TYPE hackclub_ai_errors_total counter",
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing {line:?} in {body}"
+            );
+        }
+        for kind in ErrorKind::ALL {
+            let sample = format!("hackclub_ai_errors_total{{kind=\"{}\"}} ", kind.as_str());
+            assert!(body.contains(&sample), "missing {sample:?} in {body}");
+        }
+        assert!(!body.contains("# EOF"));
+    }
+
+    #[tokio::test]
+    async fn negotiates_openmetrics_with_its_trailer() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let accept = "application/openmetrics-text;version=1.0.0,text/plain;q=0.5";
+        let (content_type, body) = scrape(proxy, accept).await;
+        assert_eq!(content_type, OPENMETRICS_CONTENT_TYPE);
+        assert!(body.ends_with("# EOF\n"), "{body}");
+        assert!(body.contains("# TYPE hackclub_ai_tokens counter\n"));
+        assert!(body.contains("\nhackclub_ai_tokens_total 0\n"));
+    }
+}
@@ -0,0 +1,100 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use serde_json::json;
+
+use crate::metrics::database::MetricsState;
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = 200, description = "Live gauges for this instance, the share of outbound requests that reused a pooled connection, and upstream latency percentiles over the last 24h; null without data", body = serde_json::Value,
+            example = json!({
+                "queue_depth": 0, "in_flight": 3, "active_streams": 2,
+                "connection_reuse_ratio": 0.97,
+                "latency_ms": { "p50": 420.0, "p95": 1800.0, "p99": 3100.5 }
+            }))
+    ),
+    tag = "Metrics"
+)]
+pub async fn stats(State(state): State<MetricsState>) -> impl IntoResponse {
+    let latencies = state.latency_percentiles().await.unwrap_or_default();
+    let percentile = |i: usize| latencies.get(i).copied();
+
+    Json(json!({
+        "queue_depth": state.queue_depth.get(),
+        "in_flight": state.in_flight(),
+        "active_streams": state.active_streams.get(),
+        "connection_reuse_ratio": state.connections.reuse_ratio(),
+        "latency_ms": {
+            "p50": percentile(0),
+            "p95": percentile(1),
+            "p99": percentile(2),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::test_support::{Upstream, db_state, proxy, state};
+
+    #[tokio::test]
+    async fn reports_live_gauges() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+        let permit = state
+            .upstream_permits
+            .clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+        let stream = state.active_streams.track();
+        let proxy = proxy(state).await;
+
+        let stats: Value = reqwest::get(format!("http://{proxy}/stats"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(stats["queue_depth"], 0);
+        assert_eq!(stats["in_flight"], 1);
+        assert_eq!(stats["active_streams"], 1);
+        for percentile in ["p50", "p95", "p99"] {
+            assert!(stats["latency_ms"][percentile].is_null());
+        }
+
+        drop((permit, stream));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn latency_percentiles_cover_the_last_day() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        client
+            .batch_execute(
+                "INSERT INTO api_logs (request, response, ip, latency_ms, created_at)
+                SELECT '{}', '{}', '10.0.0.1', n * 10, NOW() FROM generate_series(1, 100) AS n;
+                INSERT INTO api_logs (request, response, ip, latency_ms, created_at)
+                VALUES ('{}', '{}', '10.0.0.1', 999999, NOW() - INTERVAL '25 hours'),
+                ('{}', '{}', '10.0.0.1', NULL, NOW());",
+            )
+            .await
+            .unwrap();
+        let proxy = proxy(state).await;
+
+        let stats: Value = reqwest::get(format!("http://{proxy}/stats"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let latency = |p: &str| stats["latency_ms"][p].as_f64().unwrap();
+        assert!((latency("p50") - 505.0).abs() < 1.0, "{stats}");
+        assert!((latency("p95") - 950.5).abs() < 1.0, "{stats}");
+        assert!((latency("p99") - 990.1).abs() < 1.0, "{stats}");
+    }
+}
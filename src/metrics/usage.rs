@@ -0,0 +1,49 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use serde_json::json;
+
+use crate::{delegates::client_ip::ClientIp, metrics::database::MetricsState};
+
+#[utoipa::path(
+    get,
+    path = "/usage",
+    responses(
+        (status = 200, description = "Request count and token total for the caller's IP since UTC midnight", body = serde_json::Value,
+            example = json!({ "requests": 12, "tokens": 3456 }))
+    ),
+    tag = "Metrics"
+)]
+pub async fn usage(State(state): State<MetricsState>, ClientIp(ip): ClientIp) -> impl IntoResponse {
+    let (requests, tokens) = state.usage_for_ip(ip).await;
+
+    Json(json!({ "requests": requests, "tokens": tokens }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::test_support::{Upstream, db_state, eventually, log_completion, proxy};
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn counts_only_the_callers_usage() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let caller = "127.0.0.1".parse().unwrap();
+
+        log_completion(&state, caller, 5);
+        log_completion(&state, caller, 7);
+        log_completion(&state, "10.0.0.2".parse().unwrap(), 100);
+        eventually(|| async { state.usage_for_ip(caller).await == (2, 12) }).await;
+
+        let proxy = proxy(state).await;
+        let usage: Value = reqwest::get(format!("http://{proxy}/usage"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(usage["requests"], 2);
+        assert_eq!(usage["tokens"], 12);
+    }
+}
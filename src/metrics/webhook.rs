@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use reqwest::{Client, Url};
+use serde_json::Value;
+use tracing::warn;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts a log record to `LOG_WEBHOOK_URL` in the background; failures are
+/// only logged, so a slow or broken sink never holds up a response.
+pub fn mirror(client: &Client, url: &Url, record: Value) {
+    let request = client
+        .post(url.clone())
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(&record);
+
+    tokio::spawn(async move {
+        if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+            warn!("Failed to mirror log record to webhook: {e}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{Upstream, eventually, log_completion, state};
+
+    #[tokio::test]
+    async fn each_log_entry_is_posted_once() {
+        let upstream = Upstream::start().await;
+        let sink = Upstream::start().await;
+        let state = state(&upstream, &[("LOG_WEBHOOK_URL", &sink.url())]).await;
+
+        log_completion(&state, "10.0.0.1".parse().unwrap(), 4);
+        log_completion(&state, "10.0.0.2".parse().unwrap(), 6);
+        eventually(|| async { sink.requests().len() >= 2 }).await;
+
+        let records = sink.requests();
+        assert_eq!(records.len(), 2);
+        let mut tokens: Vec<_> = records.iter().map(|r| r.body["tokens"].clone()).collect();
+        tokens.sort_by_key(|t| t.as_i64());
+        assert_eq!(tokens, [4, 6]);
+        for record in &records {
+            assert_eq!(record.body["resolved_model"], "qwen/qwen3-32b");
+            assert_eq!(record.body["request"]["messages"][0]["content"], "ping");
+        }
+    }
+}
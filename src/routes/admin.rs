@@ -0,0 +1,657 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    delegates::{
+        error::{APIError, ErrorKind},
+        upstream::build_upstream_request,
+    },
+    metrics::database::{AllowedModels, MetricsState},
+};
+
+#[derive(Deserialize, ToSchema)]
+pub struct ModelsUpdate {
+    pub models: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MaintenanceUpdate {
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct LogsQuery {
+    /// Number of most recent rows to export, at most 10000
+    pub limit: Option<i64>,
+}
+
+const DEFAULT_LOG_LIMIT: i64 = 100;
+const MAX_LOG_LIMIT: i64 = 10_000;
+const REDACTED: &str = "[redacted]";
+
+pub async fn require_key(
+    State(state): State<MetricsState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    let key = &state.config.key;
+    let headers = req.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // An auth proxy in front of us may pass the key in its own header instead.
+    let trusted = state
+        .config
+        .auth_header_name
+        .as_ref()
+        .and_then(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok());
+
+    let authorized = !key.is_empty()
+        && [bearer, trusted]
+            .into_iter()
+            .flatten()
+            .any(|t| bool::from(t.as_bytes().ct_eq(key.as_bytes())));
+
+    if !authorized {
+        return Err(APIError {
+            code: StatusCode::UNAUTHORIZED,
+            kind: ErrorKind::Client,
+            body: Some("Missing or invalid admin key".into()),
+        });
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/models",
+    request_body = ModelsUpdate,
+    responses(
+        (status = 200, description = "Allowed model list replaced", body = serde_json::Value,
+            example = json!({ "models": ["llama-3.3-70b-versatile", "qwen/qwen3-32b"] })),
+        (status = 400, description = "Empty model list"),
+        (status = 401, description = "Missing or invalid admin key")
+    ),
+    tag = "Admin"
+)]
+pub async fn set_models(
+    State(state): State<MetricsState>,
+    Json(update): Json<ModelsUpdate>,
+) -> Result<impl IntoResponse, APIError> {
+    let models: AllowedModels = update
+        .models
+        .iter()
+        .map(|m| m.trim())
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    if models.is_empty() {
+        return Err(APIError {
+            code: StatusCode::BAD_REQUEST,
+            kind: ErrorKind::Client,
+            body: Some("`models` must contain at least one model".into()),
+        });
+    }
+
+    state.allowed_models.store(Arc::new(models));
+
+    Ok(Json(json!({ "models": state.allowed_models() })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    request_body = MaintenanceUpdate,
+    responses(
+        (status = 200, description = "Maintenance mode switched; chat routes return 503 while it is on", body = serde_json::Value,
+            example = json!({ "maintenance": true })),
+        (status = 401, description = "Missing or invalid admin key")
+    ),
+    tag = "Admin"
+)]
+pub async fn set_maintenance(
+    State(state): State<MetricsState>,
+    Json(update): Json<MaintenanceUpdate>,
+) -> impl IntoResponse {
+    state.maintenance.store(update.enabled, Ordering::Relaxed);
+    if update.enabled {
+        warn!("Maintenance mode enabled, chat routes will return 503");
+    } else {
+        info!("Maintenance mode disabled");
+    }
+
+    Json(json!({ "maintenance": update.enabled }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/logs",
+    params(LogsQuery),
+    responses(
+        (status = 200, description = "Most recent api_logs rows, newest first, one JSON object per line", content_type = "application/x-ndjson"),
+        (status = 401, description = "Missing or invalid admin key"),
+        (status = 503, description = "Database unavailable")
+    ),
+    tag = "Admin"
+)]
+pub async fn export_logs(
+    State(state): State<MetricsState>,
+    Query(query): Query<LogsQuery>,
+) -> Result<impl IntoResponse, APIError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LOG_LIMIT)
+        .clamp(1, MAX_LOG_LIMIT);
+
+    let rows = state.recent_logs(limit).await.ok_or(APIError {
+        code: StatusCode::SERVICE_UNAVAILABLE,
+        kind: ErrorKind::Internal,
+        body: Some("Database unavailable".into()),
+    })?;
+
+    let lines = rows.map_ok(|row| {
+        let mut line = serde_json::to_vec(&row.get::<_, Value>("line")).unwrap_or_default();
+        line.push(b'\n');
+        line
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/replay/{log_id}",
+    params(("log_id" = i32, Path, description = "`id` of the api_logs row to replay")),
+    responses(
+        (status = 200, description = "Fresh upstream response to the logged request; it is not logged again", body = serde_json::Value),
+        (status = 401, description = "Missing or invalid admin key"),
+        (status = 404, description = "No logged request with that id"),
+        (status = 502, description = "Upstream could not be reached"),
+        (status = 503, description = "Database unavailable")
+    ),
+    tag = "Admin"
+)]
+pub async fn replay_log(
+    State(state): State<MetricsState>,
+    Path(log_id): Path<i32>,
+) -> Result<Response, APIError> {
+    if state.db.is_none() {
+        return Err(APIError {
+            code: StatusCode::SERVICE_UNAVAILABLE,
+            kind: ErrorKind::Internal,
+            body: Some("Database unavailable".into()),
+        });
+    }
+
+    let mut request = state.logged_request(log_id).await.ok_or(APIError {
+        code: StatusCode::NOT_FOUND,
+        kind: ErrorKind::Client,
+        body: Some(format!("No logged request with id {log_id}").into()),
+    })?;
+
+    if let Some(obj) = request.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(false));
+    }
+
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or(&state.config.default_model)
+        .to_string();
+
+    let upstream_request = build_upstream_request(&state.client, &state.config, &model, &request)
+        .map_err(|e| APIError {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        kind: ErrorKind::Internal,
+        body: Some(format!("Failed to build upstream request: {e}").into()),
+    })?;
+
+    let response = state
+        .send_upstream(upstream_request)
+        .await
+        .map_err(|e| APIError {
+            code: StatusCode::BAD_GATEWAY,
+            kind: ErrorKind::Upstream,
+            body: Some(format!("Failed to connect to upstream service: {e}").into()),
+        })?;
+
+    let status = response.status();
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let body = response.bytes().await.map_err(|e| APIError {
+        code: StatusCode::BAD_GATEWAY,
+        kind: ErrorKind::Upstream,
+        body: Some(format!("Failed to read upstream response: {e}").into()),
+    })?;
+
+    let mut response = (status, body).into_response();
+    if let Some(content_type) = content_type {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+    response
+        .headers_mut()
+        .insert("X-Replay-Of", HeaderValue::from(log_id));
+
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    path = "/debug/config",
+    responses(
+        (status = 200, description = "Loaded non-secret settings; KEY, HMAC_SECRET, DATABASE_URL, REDIS_URL, UPSTREAM_PROXY and LOG_WEBHOOK_URL are redacted", body = serde_json::Value),
+        (status = 401, description = "Missing or invalid admin key")
+    ),
+    tag = "Admin"
+)]
+pub async fn debug_config(State(state): State<MetricsState>) -> impl IntoResponse {
+    let config = &state.config;
+    let database_connected = match &state.db {
+        Some(pool) => pool.get().await.is_ok(),
+        None => false,
+    };
+    let redacted = |value: &str| (!value.is_empty()).then_some(REDACTED);
+
+    Json(json!({
+        "key": redacted(&config.key),
+        "hmac_secret": redacted(&config.hmac_secret),
+        "database_url": redacted(&config.database_url),
+        "database_connected": database_connected,
+        "redis_url": redacted(&config.redis_url),
+        "upstream_proxy": redacted(&config.upstream_proxy),
+        "log_webhook_url": config.log_webhook_url.as_ref().map(|_| REDACTED),
+        "listen_addr": config.listen_addr.to_string(),
+        "prod_domain": config.prod_domain,
+        "docs_theme": config.docs_theme,
+        "docs_dark_mode": config.docs_dark_mode,
+        "admin_cors_origin": config.admin_cors_origin,
+        "auth_header_name": config.auth_header_name.as_ref().map(|name| name.as_str()),
+        "forward_headers": config
+            .forward_headers
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>(),
+        "default_model": config.default_model,
+        "allowed_models": state.allowed_models(),
+        "model_aliases": config.model_aliases,
+        "model_defaults": config.model_defaults,
+        "model_fallbacks": config.model_fallbacks,
+        "completions_url": config.completions_url,
+        "upstream_urls": config.upstream_urls,
+        "extra_upstream_headers": config
+            .extra_upstream_headers
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>(),
+        "strict_model": config.strict_model,
+        "strip_params": config.strip_params,
+        "min_temp": config.min_temp,
+        "max_temp": config.max_temp,
+        "json_unsupported_models": config.json_unsupported_models,
+        "blocked_phrase_count": config.blocked_phrases.len(),
+        "blocked_phrases_path": config.blocked_phrases_path,
+        "max_messages": config.max_messages,
+        "max_stop_sequences": config.max_stop_sequences,
+        "max_metadata_bytes": config.max_metadata_bytes,
+        "messages_overflow": format!("{:?}", config.messages_overflow),
+        "max_concurrent_upstream": config.max_concurrent_upstream,
+        "queue_wait_ms": config.queue_wait.as_millis() as u64,
+        "daily_token_quota": config.daily_token_quota,
+        "rate_limit_requests": config.rate_limit_requests,
+        "rate_limit_window_secs": config.rate_limit_window.as_secs(),
+        "model_rate_limits": config.model_rate_limits,
+        "circuit_failure_threshold": config.circuit_failure_threshold,
+        "circuit_window_secs": config.circuit_window.as_secs(),
+        "circuit_cooldown_secs": config.circuit_cooldown.as_secs(),
+        "cache_ttl_secs": config.cache_ttl.as_secs(),
+        "idempotency_ttl_secs": config.idempotency_ttl.as_secs(),
+        "upstream_retries": config.upstream_retries,
+        "max_upstream_retries": config.max_upstream_retries,
+        "speed_budget_ms": config.speed_budget.as_millis() as u64,
+        "speed_fallback_model": config.speed_fallback_model,
+        "stream_keepalive_secs": config.stream_keepalive.as_secs(),
+        "max_stream_secs": config.max_stream.as_secs(),
+        "slow_request_ms": config.slow_request.map(|d| d.as_millis() as u64),
+        "service_notice": config.service_notice,
+        "log_sample_rate": config.log_sample_rate,
+        "db_write_attempts": config.db_write_attempts,
+        "log_retention_days": config.log_retention_days,
+        "log_retention_interval_secs": config.log_retention_interval.as_secs(),
+        "allowed_service_tiers": config.allowed_service_tiers,
+        "geoip_db_path": config.geoip_db_path,
+        "geo_allowed_countries": config.geo_allowed_countries,
+        "geo_blocked_countries": config.geo_blocked_countries,
+        "trust_proxy": config.trust_proxy,
+        "expose_tokens_header": config.expose_tokens_header,
+        "content_filter_header": config.content_filter_header,
+        "estimate_tokens": config.estimate_tokens,
+        "startup_probe": config.startup_probe,
+        "maintenance": state.maintenance.load(Ordering::Relaxed),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use reqwest::{Method, RequestBuilder};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::test_support::{
+        Upstream, db_state, log_completion, logged_rows, ping, post_json, proxy, state,
+    };
+
+    /// A request to `path` carrying the `.env.example` KEY.
+    fn admin(proxy: SocketAddr, method: Method, path: &str) -> RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("http://{proxy}{path}"))
+            .bearer_auth("key")
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_key() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let url = format!("http://{proxy}/debug/config");
+        let client = reqwest::Client::new();
+
+        let missing = client.get(&url).send().await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        for wrong in ["ke", "keys", "KEY"] {
+            let response = client.get(&url).bearer_auth(wrong).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{wrong}");
+        }
+
+        let response = admin(proxy, Method::GET, "/debug/config")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn auth_header_name_carries_the_key() {
+        let upstream = Upstream::start().await;
+        let url = |proxy: SocketAddr| format!("http://{proxy}/debug/config");
+        let client = reqwest::Client::new();
+
+        let custom = [("AUTH_HEADER_NAME", "X-School-Key")];
+        let school = proxy(state(&upstream, &custom).await).await;
+        let accepted = client.get(url(school)).header("X-School-Key", "key");
+        assert_eq!(accepted.send().await.unwrap().status(), StatusCode::OK);
+        let wrong = client.get(url(school)).header("X-School-Key", "nope");
+        assert_eq!(
+            wrong.send().await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let unset = proxy(state(&upstream, &[]).await).await;
+        let ignored = client.get(url(unset)).header("X-School-Key", "key");
+        assert_eq!(
+            ignored.send().await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn model_swaps_are_visible_to_is_allowed_model() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+        assert!(state.is_allowed_model("qwen/qwen3-32b"));
+        assert!(!state.is_allowed_model("new/model"));
+
+        admin(proxy, Method::POST, "/admin/models")
+            .json(&json!({ "models": ["new/model"] }))
+            .send()
+            .await
+            .unwrap();
+
+        assert!(state.is_allowed_model("new/model"));
+        assert!(!state.is_allowed_model("qwen/qwen3-32b"));
+        assert_eq!(state.allowed_models(), ["new/model"]);
+    }
+
+    #[tokio::test]
+    async fn swapped_models_apply_to_the_next_request() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("STRICT_MODEL", "true")]).await).await;
+
+        let response = admin(proxy, Method::POST, "/admin/models")
+            .json(&json!({ "models": [" new/model ", ""] }))
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["models"], json!(["new/model"]));
+
+        let old = json!({ "model": "qwen/qwen3-32b", "messages": ping() });
+        let response = post_json(proxy, "/chat/completions", old).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let new = json!({ "model": "new/model", "messages": ping() });
+        let response = post_json(proxy, "/chat/completions", new).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(upstream.requests()[0].body["model"], "new/model");
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_turns_chat_away_but_keeps_health_up() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let toggle = |enabled: bool| {
+            admin(proxy, Method::POST, "/admin/maintenance")
+                .json(&json!({ "enabled": enabled }))
+                .send()
+        };
+        let chat = || post_json(proxy, "/chat/completions", json!({ "messages": ping() }));
+
+        let body: Value = toggle(true).await.unwrap().json().await.unwrap();
+        assert_eq!(body["maintenance"], true);
+        let response = chat().await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(
+            error["error"]["message"],
+            "Down for maintenance, please try again later"
+        );
+        let readyz = reqwest::get(format!("http://{proxy}/readyz"))
+            .await
+            .unwrap();
+        assert_eq!(readyz.status(), StatusCode::OK);
+        assert!(upstream.requests().is_empty());
+
+        toggle(false).await.unwrap();
+        assert_eq!(chat().await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn empty_model_lists_are_rejected() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[]).await;
+        let before = state.allowed_models();
+        let proxy = proxy(state.clone()).await;
+
+        let response = admin(proxy, Method::POST, "/admin/models")
+            .json(&json!({ "models": [" "] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.allowed_models(), before);
+    }
+
+    #[tokio::test]
+    async fn debug_config_redacts_secrets() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[("HMAC_SECRET", "hunter2")]).await;
+        let proxy = proxy(state).await;
+
+        let response = admin(proxy, Method::GET, "/debug/config")
+            .send()
+            .await
+            .unwrap();
+        let text = response.text().await.unwrap();
+        assert!(!text.contains("hunter2"));
+
+        let config: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(config["key"], REDACTED);
+        assert_eq!(config["hmac_secret"], REDACTED);
+        assert_eq!(config["database_url"], Value::Null);
+        assert_eq!(config["database_connected"], false);
+        assert_eq!(config["default_model"], "qwen/qwen3-32b");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn debug_config_reports_a_connected_database() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let url = state.config.database_url.clone();
+        let proxy = proxy(state).await;
+
+        let response = admin(proxy, Method::GET, "/debug/config")
+            .send()
+            .await
+            .unwrap();
+        let text = response.text().await.unwrap();
+        assert!(!text.contains(&url));
+
+        let config: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(config["database_url"], REDACTED);
+        assert_eq!(config["database_connected"], true);
+    }
+
+    #[tokio::test]
+    async fn logs_and_replay_need_a_database() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        for (method, path) in [
+            (Method::GET, "/admin/logs"),
+            (Method::POST, "/admin/replay/1"),
+        ] {
+            let response = admin(proxy, method, path).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn export_logs_streams_the_newest_rows_as_ndjson() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        for tokens in [1, 2, 3] {
+            log_completion(&state, "10.0.0.1".parse().unwrap(), tokens);
+            logged_rows(&state, tokens as usize).await;
+        }
+        let proxy = proxy(state).await;
+
+        let response = admin(proxy, Method::GET, "/admin/logs?limit=2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+
+        let text = response.text().await.unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let tokens: Vec<_> = lines.iter().map(|line| line["tokens"].clone()).collect();
+        assert_eq!(tokens, [json!(3), json!(2)]);
+
+        let fields = [
+            "id",
+            "created_at",
+            "requested_model",
+            "resolved_model",
+            "tokens",
+            "request",
+            "response",
+            "metadata",
+        ];
+        for field in fields {
+            assert!(lines[0].get(field).is_some(), "missing {field}");
+        }
+        assert_eq!(lines[0]["request"]["model"], "qwen/qwen3-32b");
+        assert_eq!(lines[0]["response"]["object"], "chat.completion");
+        assert!(lines[0].get("ip").is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn replay_proxies_the_logged_request_without_logging_it() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let logged = json!({ "model": "openai/gpt-oss-120b", "messages": ping(), "stream": true });
+        let client = state.db.as_ref().unwrap().get().await.unwrap();
+        let row = client
+            .query_one(
+                "INSERT INTO api_logs (request, response, ip, tokens)
+                VALUES ($1, '{}', '10.0.0.1', 4) RETURNING id",
+                &[&logged],
+            )
+            .await
+            .unwrap();
+        let id: i32 = row.get("id");
+        let proxy = proxy(state.clone()).await;
+
+        let response = admin(proxy, Method::POST, &format!("/admin/replay/{id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let replayed: Value = response.json().await.unwrap();
+        assert_eq!(replayed["choices"][0]["message"]["content"], "pong");
+
+        let sent = upstream.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body["model"], "openai/gpt-oss-120b");
+        assert_eq!(sent[0].body["messages"], logged["messages"]);
+        assert_eq!(sent[0].body["stream"], false);
+
+        sleep(Duration::from_millis(200)).await;
+        logged_rows(&state, 1).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn replaying_an_unknown_id_is_404() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(db_state(&upstream, &[]).await).await;
+
+        let response = admin(proxy, Method::POST, "/admin/replay/42")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(upstream.requests().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use axum::{
+    Json,
+    body::to_bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::future::join_all;
+use serde_json::{Value, json};
+
+use crate::{
+    delegates::{
+        client_ip::ClientIp,
+        error::{APIError, ErrorKind},
+    },
+    metrics::database::MetricsState,
+    routes::completions::complete_json,
+};
+
+const PATH: &str = "/batch/chat/completions";
+const MAX_BATCH_SIZE: usize = 20;
+
+#[utoipa::path(
+    post,
+    path = "/batch/chat/completions",
+    request_body = Vec<serde_json::Value>,
+    responses(
+        (status = 200, description = "One result per request, in order", body = Vec<serde_json::Value>,
+            example = json!([{ "status": 200, "body": { "object": "chat.completion" } }])),
+        (status = 400, description = "Body is not a non-empty array of at most 20 requests")
+    ),
+    tag = "Chat"
+)]
+pub async fn batch_completions(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    Json(requests): Json<Vec<Value>>,
+) -> Result<Json<Vec<Value>>, APIError> {
+    if requests.is_empty() || requests.len() > MAX_BATCH_SIZE {
+        return Err(APIError {
+            code: StatusCode::BAD_REQUEST,
+            kind: ErrorKind::Client,
+            body: Some(format!("A batch must contain 1 to {MAX_BATCH_SIZE} requests").into()),
+        });
+    }
+
+    // Each sub-request waits for its own upstream permit, so a batch never
+    // exceeds MAX_CONCURRENT_UPSTREAM.
+    let results = requests.into_iter().map(|mut request| {
+        let state = state.clone();
+        async move {
+            if let Some(obj) = request.as_object_mut() {
+                obj.insert("stream".to_string(), Value::Bool(false));
+            }
+
+            let bytes = serde_json::to_vec(&request).map_or(0, |b| b.len());
+            let response = complete_json(&state, ip, "POST", PATH, bytes, request)
+                .await
+                .unwrap_or_else(IntoResponse::into_response);
+            result_entry(response).await
+        }
+    });
+
+    Ok(Json(join_all(results).await))
+}
+
+async fn result_entry(response: Response) -> Value {
+    let status = response.status().as_u16();
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+
+    json!({ "status": status, "body": body })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{Json, response::IntoResponse};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::test_support::{Upstream, completion, ping, post_json, proxy, state};
+
+    #[tokio::test]
+    async fn results_come_back_in_request_order() {
+        // The first prompt answers last, so ordering by completion would swap them.
+        let upstream = Upstream::responding(|body| async move {
+            let prompt = body["messages"][0]["content"].as_str().unwrap().to_string();
+            if prompt == "first" {
+                sleep(Duration::from_millis(200)).await;
+            }
+            Json(completion(&body["model"], &format!("re: {prompt}"))).into_response()
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let prompt =
+            |content: &str| json!({ "messages": [{ "role": "user", "content": content }] });
+        let batch = json!([prompt("first"), prompt("second")]);
+        let results: Value = post_json(proxy, PATH, batch).await.json().await.unwrap();
+
+        let replies: Vec<&str> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                assert_eq!(result["status"], 200);
+                result["body"]["choices"][0]["message"]["content"]
+                    .as_str()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(replies, ["re: first", "re: second"]);
+    }
+
+    #[tokio::test]
+    async fn each_request_is_validated_on_its_own() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("STRICT_MODEL", "true")]).await).await;
+
+        let batch = json!([
+            { "model": "gpt-4o", "messages": ping() },
+            { "messages": ping(), "stream": true },
+        ]);
+        let results: Value = post_json(proxy, PATH, batch).await.json().await.unwrap();
+
+        assert_eq!(results[0]["status"], 400);
+        assert!(results[0]["body"]["error"]["message"].is_string());
+        assert_eq!(results[1]["status"], 200);
+        assert_eq!(
+            results[1]["body"]["choices"][0]["message"]["content"],
+            "pong"
+        );
+
+        let sent = upstream.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body["stream"], false);
+    }
+
+    #[tokio::test]
+    async fn batches_must_hold_one_to_twenty_requests() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let request = json!({ "messages": ping() });
+
+        for batch in [json!([]), json!(vec![request; MAX_BATCH_SIZE + 1])] {
+            let response = post_json(proxy, PATH, batch).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(upstream.requests().is_empty());
+    }
+}
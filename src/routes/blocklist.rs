@@ -0,0 +1,173 @@
+use std::fs;
+
+use aho_corasick::AhoCorasick;
+use axum::http::StatusCode;
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    delegates::error::{APIError, ErrorKind},
+    metrics::database::MetricsState,
+};
+
+/// Builds a case-insensitive matcher from BLOCKED_PHRASES and the file at
+/// BLOCKED_PHRASES_PATH, one phrase per line.
+pub fn from_config(config: &Config) -> Option<AhoCorasick> {
+    let mut phrases = config.blocked_phrases.clone();
+
+    if !config.blocked_phrases_path.is_empty() {
+        match fs::read_to_string(&config.blocked_phrases_path) {
+            Ok(contents) => phrases.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string),
+            ),
+            Err(e) => warn!(
+                "Failed to read BLOCKED_PHRASES_PATH {}: {}",
+                config.blocked_phrases_path, e
+            ),
+        }
+    }
+
+    if phrases.is_empty() {
+        return None;
+    }
+
+    match AhoCorasick::builder()
+        .ascii_case_insensitive(true)
+        .build(&phrases)
+    {
+        Ok(matcher) => {
+            info!("Blocking prompts containing {} phrases", phrases.len());
+            Some(matcher)
+        }
+        Err(e) => {
+            error!("Failed to build the blocked phrase matcher: {}", e);
+            None
+        }
+    }
+}
+
+pub fn check_prompt(state: &MetricsState, json: &Value) -> Result<(), APIError> {
+    let Some(matcher) = &state.blocked_phrases else {
+        return Ok(());
+    };
+
+    let blocked = json
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|message| message.get("role").and_then(Value::as_str) == Some("user"))
+        .filter_map(|message| message.get("content"))
+        .any(|content| match content {
+            Value::String(text) => matcher.is_match(text),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part.get("text")?.as_str())
+                .any(|text| matcher.is_match(text)),
+            _ => false,
+        });
+
+    if blocked {
+        return Err(APIError {
+            code: StatusCode::FORBIDDEN,
+            kind: ErrorKind::Client,
+            body: Some("Request contains blocked content".into()),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Arc};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{Upstream, ping, post_json, proxy, state};
+
+    async fn blocking(overrides: &[(&str, &str)]) -> MetricsState {
+        let config = Arc::new(Config::example(overrides).unwrap());
+        MetricsState::init(config, reqwest::Client::new(), Arc::default()).await
+    }
+
+    fn said(role: &str, content: Value) -> Value {
+        json!({ "messages": [{ "role": role, "content": content }] })
+    }
+
+    #[tokio::test]
+    async fn user_messages_with_a_phrase_are_rejected() {
+        let state = blocking(&[("BLOCKED_PHRASES", "free vbucks,jailbreak")]).await;
+
+        let shouted = said("user", json!("how do I get FREE VBucks"));
+        let err = check_prompt(&state, &shouted).unwrap_err();
+        assert_eq!(err.code, StatusCode::FORBIDDEN);
+
+        let parts = said(
+            "user",
+            json!([{ "type": "text", "text": "a Jailbreak please" }]),
+        );
+        assert!(check_prompt(&state, &parts).is_err());
+    }
+
+    #[tokio::test]
+    async fn clean_prompts_and_other_roles_pass() {
+        let state = blocking(&[("BLOCKED_PHRASES", "jailbreak")]).await;
+
+        assert!(check_prompt(&state, &said("user", json!("tell me a joke"))).is_ok());
+        assert!(check_prompt(&state, &said("system", json!("never jailbreak"))).is_ok());
+        assert!(check_prompt(&state, &json!({ "prompt": "jailbreak" })).is_ok());
+    }
+
+    #[tokio::test]
+    async fn blocked_chat_requests_never_reach_upstream() {
+        let upstream = Upstream::start().await;
+        let overrides = [("BLOCKED_PHRASES", "free vbucks")];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let blocked = said("user", json!("where are the Free VBucks"));
+        let response = post_json(proxy, "/chat/completions", blocked).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(upstream.requests().is_empty());
+
+        let clean = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(clean.status(), StatusCode::OK);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn phrases_load_from_the_file_and_the_list() {
+        let path = env::temp_dir().join(format!("blocked_{:016x}.txt", rand::random::<u64>()));
+        fs::write(&path, "  crypto scam \n\nfree vbucks\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let overrides = [
+            ("BLOCKED_PHRASES", "jailbreak"),
+            ("BLOCKED_PHRASES_PATH", &path),
+        ];
+        let state = blocking(&overrides).await;
+        for phrase in ["a Crypto Scam", "free vbucks", "jailbreak"] {
+            assert!(
+                check_prompt(&state, &said("user", json!(phrase))).is_err(),
+                "{phrase}"
+            );
+        }
+        assert!(check_prompt(&state, &said("user", json!("crypto"))).is_ok());
+    }
+
+    #[test]
+    fn unreadable_files_fall_back_to_the_list() {
+        let missing = [("BLOCKED_PHRASES_PATH", "/nonexistent/blocked.txt")];
+        assert!(from_config(&Config::example(&missing).unwrap()).is_none());
+
+        let listed = [("BLOCKED_PHRASES", "jailbreak"), missing[0]];
+        let matcher = from_config(&Config::example(&listed).unwrap()).unwrap();
+        assert!(matcher.is_match("JAILBREAK"));
+    }
+}
@@ -0,0 +1,194 @@
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Value, from_slice};
+
+use crate::{
+    delegates::error::{APIError, ErrorKind},
+    routes::{
+        completions::{ParsedRequest, wants_stream},
+        translate::{
+            anthropic_to_openai, chat_to_completion, completion_to_chat, openai_to_anthropic,
+        },
+    },
+};
+
+pub async fn anthropic_messages(req: Request, next: Next) -> Result<Response, APIError> {
+    translate_exchange(
+        req,
+        next,
+        "/v1/messages",
+        anthropic_to_openai,
+        openai_to_anthropic,
+    )
+    .await
+}
+
+pub async fn legacy_completions(req: Request, next: Next) -> Result<Response, APIError> {
+    translate_exchange(
+        req,
+        next,
+        "/v1/completions",
+        completion_to_chat,
+        chat_to_completion,
+    )
+    .await
+}
+
+async fn translate_exchange(
+    req: Request,
+    next: Next,
+    path: &str,
+    to_chat: fn(&Value) -> Result<Value, String>,
+    from_chat: fn(&Value) -> Value,
+) -> Result<Response, APIError> {
+    let (mut parts, body) = req.into_parts();
+
+    let bytes = to_bytes(body, usize::MAX).await.map_err(|_| APIError {
+        code: StatusCode::BAD_REQUEST,
+        kind: ErrorKind::Client,
+        body: Some("Failed to read request body".into()),
+    })?;
+
+    let json: Value = from_slice(&bytes).map_err(|_| APIError {
+        code: StatusCode::BAD_REQUEST,
+        kind: ErrorKind::Client,
+        body: Some("Invalid JSON".into()),
+    })?;
+
+    if wants_stream(&parts.headers, &json) {
+        return Err(APIError {
+            code: StatusCode::BAD_REQUEST,
+            kind: ErrorKind::Client,
+            body: Some(format!("Streaming is not supported on {path}").into()),
+        });
+    }
+
+    let translated = to_chat(&json).map_err(|e| APIError {
+        code: StatusCode::BAD_REQUEST,
+        kind: ErrorKind::Client,
+        body: Some(format!("Invalid request: {e}").into()),
+    })?;
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.extensions.insert(ParsedRequest {
+        json: translated,
+        bytes: bytes.len(),
+    });
+    let response = next.run(Request::from_parts(parts, Body::empty())).await;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.map_err(|_| APIError {
+        code: StatusCode::BAD_GATEWAY,
+        kind: ErrorKind::Upstream,
+        body: Some("Failed to read upstream response".into()),
+    })?;
+
+    let json: Value = from_slice(&bytes).map_err(|_| APIError {
+        code: StatusCode::BAD_GATEWAY,
+        kind: ErrorKind::Upstream,
+        body: Some("Invalid response from upstream service".into()),
+    })?;
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let translated = Json(from_chat(&json)).into_response();
+    Ok(Response::from_parts(parts, translated.into_body()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{BROKEN_MODEL, Upstream, post_json, proxy, state};
+
+    #[tokio::test]
+    async fn anthropic_messages_round_trip_through_chat() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let request = json!({
+            "max_tokens": 16,
+            "system": "Be brief.",
+            "messages": [{ "role": "user", "content": "ping" }],
+        });
+        let response = post_json(proxy, "/v1/messages", request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let message: Value = response.json().await.unwrap();
+        assert_eq!(message["type"], "message");
+        assert_eq!(message["content"][0]["text"], "pong");
+        assert_eq!(message["stop_reason"], "end_turn");
+        assert_eq!(
+            message["usage"],
+            json!({ "input_tokens": 3, "output_tokens": 1 })
+        );
+
+        let sent = &upstream.requests()[0].body;
+        assert_eq!(
+            sent["messages"][0],
+            json!({ "role": "system", "content": "Be brief." })
+        );
+        assert_eq!(
+            sent["messages"][1],
+            json!({ "role": "user", "content": "ping" })
+        );
+        assert_eq!(sent["max_tokens"], 16);
+    }
+
+    #[tokio::test]
+    async fn legacy_prompts_round_trip_through_chat() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let response = post_json(proxy, "/v1/completions", json!({ "prompt": "ping" })).await;
+        let completion: Value = response.json().await.unwrap();
+        assert_eq!(completion["object"], "text_completion");
+        assert_eq!(completion["choices"][0]["text"], "pong");
+
+        let sent = &upstream.requests()[0].body;
+        assert_eq!(
+            sent["messages"],
+            json!([{ "role": "user", "content": "ping" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_and_untranslatable_bodies_are_rejected() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let streaming = json!({ "prompt": "ping", "stream": true });
+        let response = post_json(proxy, "/v1/completions", streaming).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = post_json(proxy, "/v1/completions", json!({ "prompt": ["a", "b"] })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(
+            error["error"]["message"],
+            "Invalid request: only a single prompt is supported"
+        );
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn upstream_errors_pass_through_untranslated() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let request = json!({ "model": BROKEN_MODEL, "prompt": "ping" });
+        let response = post_json(proxy, "/v1/completions", request).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error: Value = response.json().await.unwrap();
+        assert!(error.get("error").is_some(), "{error}");
+    }
+}
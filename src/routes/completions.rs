@@ -1,174 +1,2030 @@
-use std::net::SocketAddr;
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
 
 use axum::{
-    body::{Body, to_bytes},
-    extract::{ConnectInfo, Json, Request, State},
-    http::{Method, StatusCode, header},
+    body::{Body, Bytes, to_bytes},
+    extract::{Extension, FromRequestParts, Query, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header, request::Parts, response::Builder},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use futures::StreamExt;
-use serde_json::{Value, from_slice};
-use tracing::error;
+use futures::{StreamExt, future, stream};
+use serde::Deserialize;
+use serde_json::{Value, from_slice, json};
+use tokio::{
+    pin, select,
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+use tracing::{debug, error, field::Empty, info, info_span, warn};
+use utoipa::IntoParams;
 
 use crate::{
-    CLIENT, COMPLETIONS_URL, DEFAULT_MODEL,
-    delegates::error::APIError,
-    is_allowed_model,
-    metrics::database::{MetricsState, extract_tokens},
+    config::Config,
+    delegates::{
+        cache::{completion_cache_key, idempotency_cache_key},
+        client_ip::ClientIp,
+        error::{APIError, ErrorKind, ValidationErrors},
+        sse::UsageAccumulator,
+        timing::timed,
+        upstream::build_upstream_request,
+    },
+    metrics::{
+        access_log::{ServedModel, TokensUsed},
+        database::{LogEntry, MetricsState, extract_tokens, finish_reason, is_refusal},
+    },
+    routes::{
+        blocklist::check_prompt,
+        limits::{check_maintenance, quota_exceeded, rate_limited},
+        schema::ChatCompletionRequest,
+    },
 };
 
-pub async fn validate_model(req: Request, next: Next) -> Result<Response, APIError> {
-    let (parts, body) = req.into_parts();
+/// Query flags for `/chat/completions`. `validate_only` wins over streaming,
+/// so a streaming request gets its normalized JSON back, except that an
+/// `Accept` header naming only `text/event-stream` can't take JSON and is
+/// rejected with 400. `pretty` applies to whatever JSON is returned and is
+/// ignored for streams.
+#[derive(Default, Deserialize, IntoParams)]
+pub struct CompletionQuery {
+    /// Pretty-print a non-streaming JSON response. Streams are unaffected.
+    #[serde(default)]
+    pub pretty: bool,
+    /// Return the request as it would be sent upstream without sending it.
+    #[serde(default)]
+    pub validate_only: bool,
+}
 
-    let bytes = to_bytes(body, usize::MAX).await.map_err(|_| APIError {
-        code: StatusCode::BAD_REQUEST,
-        body: Some("Failed to read request body"),
-    })?;
+/// A body an outer middleware already parsed (and possibly rewrote), which
+/// `validate_model` takes instead of parsing the body again. `bytes` is the
+/// size the client sent.
+#[derive(Clone)]
+pub struct ParsedRequest {
+    pub json: Value,
+    pub bytes: usize,
+}
 
-    let mut json: Value = from_slice(&bytes).map_err(|_| APIError {
-        code: StatusCode::BAD_REQUEST,
-        body: Some("Invalid JSON"),
-    })?;
+/// The request body as `validate_model` left it, handed to the handler in
+/// the request extensions so it is parsed once instead of being serialized
+/// and parsed again.
+#[derive(Clone)]
+pub struct NormalizedRequest(pub Value);
 
-    if let Some(obj) = json.as_object_mut() {
-        if let Some(tier) = obj.get("service_tier").and_then(Value::as_str) {
-            if tier != "flex" && tier != "on_demand" {
-                obj.remove("service_tier");
-            }
-        } else {
-            obj.remove("service_tier");
+impl<S: Send + Sync> FromRequestParts<S> for NormalizedRequest {
+    type Rejection = APIError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.remove::<Self>().ok_or(APIError {
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            kind: ErrorKind::Internal,
+            body: Some("Request was not validated".into()),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestMeta {
+    pub method: String,
+    pub path: String,
+    pub bytes: usize,
+    pub requested_model: Option<String>,
+    pub resolved_model: String,
+}
+
+const STREAM_CHANNEL_SIZE: usize = 32;
+const KEEPALIVE: &[u8] = b": keepalive\n\n";
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+pub async fn validate_model(
+    State(state): State<MetricsState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    let config = &state.config;
+
+    if req.method() == Method::OPTIONS {
+        return Ok(next.run(req).await);
+    }
+
+    let (mut parts, body) = req.into_parts();
+
+    let (mut json, bytes) = match parts.extensions.remove::<ParsedRequest>() {
+        Some(parsed) => (parsed.json, parsed.bytes),
+        None => {
+            let bytes = to_bytes(body, usize::MAX).await.map_err(|_| APIError {
+                code: StatusCode::BAD_REQUEST,
+                kind: ErrorKind::Client,
+                body: Some("Failed to read request body".into()),
+            })?;
+
+            let json: Value = from_slice(&bytes).map_err(|_| APIError {
+                code: StatusCode::BAD_REQUEST,
+                kind: ErrorKind::Client,
+                body: Some("Invalid JSON".into()),
+            })?;
+            (json, bytes.len())
         }
+    };
 
-        let needs_update = obj
-            .get("model")
-            .and_then(Value::as_str)
-            .map_or(true, |m| !is_allowed_model(m));
+    check_prompt(&state, &json)?;
 
-        if needs_update {
-            obj.insert(
-                "model".to_string(),
-                Value::String(DEFAULT_MODEL.to_string()),
-            );
+    let requested_model = match prepare_request(&state, &mut json) {
+        Ok(model) => model,
+        Err(errors) => return Ok(errors.into_response()),
+    };
+
+    let stream = wants_stream(&parts.headers, &json);
+    if let Some(obj) = json.as_object_mut()
+        && obj.get("stream").and_then(Value::as_bool).unwrap_or(false) != stream
+    {
+        obj.insert("stream".to_string(), Value::Bool(stream));
+        if !stream {
+            obj.remove("stream_options");
         }
     }
 
-    let body = serde_json::to_vec(&json).map_err(|_| APIError {
-        code: StatusCode::INTERNAL_SERVER_ERROR,
-        body: Some("Failed to serialize request"),
-    })?;
+    let resolved_model = json
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or(&config.default_model)
+        .to_string();
 
-    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+    let method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+
+    let mut req = Request::from_parts(parts, Body::empty());
+    req.extensions_mut().insert(RequestMeta {
+        method,
+        path,
+        bytes,
+        requested_model,
+        resolved_model,
+    });
+    req.extensions_mut().insert(NormalizedRequest(json));
+
+    Ok(next.run(req).await)
+}
+
+/// Whether the client wants server-sent events. An `Accept` header naming
+/// `text/event-stream` turns streaming on; otherwise the body's `stream`
+/// field decides. `application/json` doesn't turn it off, since SDKs and
+/// HTTP clients send it by default on streaming requests too.
+pub fn wants_stream(headers: &HeaderMap, json: &Value) -> bool {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    accept.contains("text/event-stream")
+        || json.get("stream").and_then(Value::as_bool).unwrap_or(false)
+}
+
+/// Runs a completion for callers outside the HTTP middleware stack, such as
+/// the websocket and batch routes, with the same maintenance, quota,
+/// validation and per-model rate limit checks the HTTP middleware applies.
+/// Each call re-checks them, so a long-lived socket or a large batch can't
+/// outlast a quota or a maintenance switch.
+pub async fn complete_json(
+    state: &MetricsState,
+    ip: IpAddr,
+    method: &str,
+    path: &str,
+    bytes: usize,
+    mut json: Value,
+) -> Result<Response, APIError> {
+    check_maintenance(state)?;
+    if let Some(response) = quota_exceeded(state, ip).await {
+        return Ok(response);
+    }
+
+    check_prompt(state, &json)?;
+
+    let requested_model = match prepare_request(state, &mut json) {
+        Ok(model) => model,
+        Err(errors) => return Ok(errors.into_response()),
+    };
+    let resolved_model = json
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or(&state.config.default_model)
+        .to_string();
+
+    if let Some(response) = rate_limited(state, ip, &resolved_model) {
+        return Ok(response);
+    }
+
+    let meta = RequestMeta {
+        method: method.to_string(),
+        path: path.to_string(),
+        bytes,
+        requested_model,
+        resolved_model,
+    };
+
+    Ok(completions(
+        State(state.clone()),
+        ClientIp(ip),
+        Extension(meta),
+        Query(CompletionQuery::default()),
+        HeaderMap::new(),
+        NormalizedRequest(json),
+    )
+    .await
+    .into_response())
+}
+
+/// Runs every registered transform over the request, collecting every
+/// problem instead of stopping at the first one.
+pub fn prepare_request(
+    state: &MetricsState,
+    json: &mut Value,
+) -> Result<Option<String>, ValidationErrors> {
+    let requested_model = json
+        .get("model")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let mut problems = Vec::new();
+    for transform in state.transforms.iter() {
+        transform.apply(state, json, &mut problems);
+    }
+
+    if !problems.is_empty() {
+        return Err(ValidationErrors(problems));
+    }
+
+    Ok(requested_model)
 }
 
 #[utoipa::path(
     post,
     path = "/chat/completions",
-    request_body(
-        content = serde_json::Value,
-        example = json!({
-            "messages": [{"role": "user", "content": "Tell me a joke!"}]
-        })
-    ),
+    params(CompletionQuery),
+    request_body = ChatCompletionRequest,
     responses(
         (status = 200, description = "Chat completion successful", body = serde_json::Value),
-        (status = 400, description = "Bad request"),
-        (status = 502, description = "Upstream service error")
+        (status = 400, description = "Bad request, or `validate_only` with an `Accept` header that only takes `text/event-stream`"),
+        (status = 429, description = "Rate limit or daily token quota exceeded"),
+        (status = 502, description = "Upstream service error, including non-JSON error pages from the upstream"),
+        (status = 503, description = "Service not configured, too many requests in flight, or the upstream is failing")
     ),
     tag = "Chat",
-    description = "OpenAI/Groq compatible chat completions endpoint. See: https://platform.openai.com/docs/api-reference/introduction and https://console.groq.com/docs/api-reference#chat-create"
+    description = "OpenAI/Groq compatible chat completions endpoint. See: https://platform.openai.com/docs/api-reference/introduction and https://console.groq.com/docs/api-reference#chat-create\n\nAn `Accept` header naming `text/event-stream` turns streaming on; otherwise the body's `stream` field decides. Streamed responses report their token count in a closing `: x-tokens-used <n>` comment instead of the `X-Tokens-Used` header.\n\nWith `?validate_only=true` the normalized request is returned as JSON instead of being sent upstream, even when it asks to stream.\n\nWhen IDEMPOTENCY_TTL_SECS is set, a repeated `Idempotency-Key` from the same IP gets the first response back with `Idempotent-Replayed: true`; replays do not count toward usage or the daily quota."
 )]
 pub async fn completions(
     State(state): State<MetricsState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Json(request): Json<Value>,
+    ClientIp(ip): ClientIp,
+    Extension(mut meta): Extension<RequestMeta>,
+    Query(query): Query<CompletionQuery>,
+    headers: HeaderMap,
+    NormalizedRequest(mut request): NormalizedRequest,
 ) -> impl IntoResponse {
-    let response = CLIENT
-        .request(Method::POST, COMPLETIONS_URL)
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| {
-            error!("Failed to send request to Groq: {}", e);
-            APIError {
-                code: StatusCode::BAD_GATEWAY,
-                body: Some("Failed to connect to upstream service"),
-            }
-        })?;
+    if query.validate_only {
+        return validated(&headers, &request, query.pretty);
+    }
+
+    if !state.config.is_configured() {
+        return Err(APIError {
+            code: StatusCode::SERVICE_UNAVAILABLE,
+            kind: ErrorKind::Internal,
+            body: Some("Service not configured".into()),
+        });
+    }
+
+    let mut cache_key = state
+        .cache
+        .as_ref()
+        .filter(|_| !state.config.cache_ttl.is_zero())
+        .and_then(|_| completion_cache_key(&request));
+
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok())
+        .filter(|key| !key.is_empty() && !state.config.idempotency_ttl.is_zero())
+        .map(|key| idempotency_cache_key(ip, key));
+
+    // A replay answers a client's retry of a request that was already logged
+    // and charged, so unlike a cache hit it is not counted again.
+    if let (Some(cache), Some(key)) = (&state.cache, &idempotency_key)
+        && let Some(body) = cache.get(key).await
+    {
+        let body = if query.pretty { pretty(body) } else { body };
+        let builder = cached_response("Idempotent-Replayed", "true");
+        return Ok(builder.body(Body::from(body)).unwrap());
+    }
+
+    if let (Some(cache), Some(key)) = (&state.cache, &cache_key)
+        && let Some(body) = cache.get(key).await
+    {
+        // Hits skip the upstream but still count toward usage and the quota.
+        let tokens = log_cached(&state, ip, &meta, &request, &body);
+        let body = if query.pretty { pretty(body) } else { body };
+        let builder = with_tokens(&state.config, cached_response("X-Cache", "HIT"), tokens);
+        return Ok(builder.body(Body::from(body)).unwrap());
+    }
+
+    let queued = state.queue_depth.track();
+    let permit = timeout(
+        state.config.queue_wait,
+        state.upstream_permits.clone().acquire_owned(),
+    )
+    .await
+    .map_err(|_| APIError {
+        code: StatusCode::SERVICE_UNAVAILABLE,
+        kind: ErrorKind::Internal,
+        body: Some("Too many requests in flight, try again shortly".into()),
+    })?
+    .map_err(|_| APIError {
+        code: StatusCode::SERVICE_UNAVAILABLE,
+        kind: ErrorKind::Internal,
+        body: None,
+    })?;
+    drop(queued);
+
+    let mut model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or(&state.config.default_model)
+        .to_string();
+
+    let started = Instant::now();
+    let retries = max_retries(&headers, &state.config);
+    let mut attempt = 0;
+    // Failed sends so far, whether retried on the same model or handed to a
+    // fallback, which grows the backoff.
+    let mut failures = 0;
+    let mut missed_budget = false;
+    let primary = model.clone();
+    let mut fallbacks = state
+        .config
+        .model_fallbacks
+        .get(&model)
+        .into_iter()
+        .flatten();
+    let sent = loop {
+        if !state.circuit.allow() {
+            return Err(APIError {
+                code: StatusCode::SERVICE_UNAVAILABLE,
+                kind: ErrorKind::Upstream,
+                body: Some("Upstream service is temporarily unavailable".into()),
+            });
+        }
+
+        let upstream_request =
+            build_upstream_request(&state.client, &state.config, &model, &request)
+                .map_err(build_failed)?;
+
+        let fallback = state.config.speed_fallback_model.as_str();
+        let send_span = || info_span!("upstream_send", model = %model, elapsed_ms = Empty);
+        let sent =
+            if state.config.speed_budget.is_zero() || fallback.is_empty() || model == fallback {
+                timed(send_span(), state.send_upstream(upstream_request)).await
+            } else {
+                match timeout(
+                    state.config.speed_budget,
+                    timed(send_span(), state.send_upstream(upstream_request)),
+                )
+                .await
+                {
+                    Ok(sent) => sent,
+                    Err(_) => {
+                        warn!("{model} missed the speed budget, retrying with {fallback}");
+                        missed_budget = true;
+                        model = fallback.to_string();
+                        request["model"] = Value::String(model.clone());
+                        meta.resolved_model = model.clone();
+                        cache_key = None;
+
+                        let fallback_request =
+                            build_upstream_request(&state.client, &state.config, &model, &request)
+                                .map_err(build_failed)?;
+                        let span = info_span!("upstream_send", model = %model, elapsed_ms = Empty);
+                        timed(span, state.send_upstream(fallback_request)).await
+                    }
+                }
+            };
+
+        let failed = sent
+            .as_ref()
+            .map_or(true, |response| response.status().is_server_error());
+        if failed && attempt < retries {
+            attempt += 1;
+            failures += 1;
+            warn!("Upstream attempt {attempt} for {model} failed, retrying");
+            sleep(retry_backoff(failures)).await;
+            continue;
+        }
+
+        // Retries are spent on this model; move down its MODEL_FALLBACKS chain.
+        if failed && let Some(next) = fallbacks.next() {
+            failures += 1;
+            warn!("{model} failed, falling back to {next}");
+            sleep(retry_backoff(failures)).await;
+            model = next.clone();
+            request["model"] = Value::String(model.clone());
+            meta.resolved_model = model.clone();
+            cache_key = None;
+            attempt = 0;
+            continue;
+        }
+
+        break sent;
+    };
+
+    if model != primary {
+        info!("Request for {primary} served by {model}");
+    }
+
+    // The breaker hears once per client request, so a client asking for many
+    // retries can't trip it for everyone. A request that only got through on
+    // a retry, a fallback or after missing the speed budget still counts as
+    // a failure.
+    let failed = sent
+        .as_ref()
+        .map_or(true, |response| response.status().is_server_error());
+    if failed || failures > 0 || missed_budget {
+        state.circuit.record_failure();
+    } else {
+        state.circuit.record_success();
+    }
+
+    let response = sent.map_err(|e| {
+        error!("Failed to send request to Groq: {}", e);
+        APIError {
+            code: StatusCode::BAD_GATEWAY,
+            kind: ErrorKind::Upstream,
+            body: Some("Failed to connect to upstream service".into()),
+        }
+    })?;
+
+    // Successful non-JSON bodies are passed through as they came. Error pages
+    // (say an HTML 502 from a load balancer) become an error envelope below,
+    // since clients only know how to read those, and the page is logged.
+    let upstream_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    if let Some(content_type) = &upstream_type
+        && !is_json_or_stream(content_type)
+        && response.status().is_success()
+    {
+        let status = response.status();
+        let body = response.bytes().await.unwrap_or_default();
+        warn!("Upstream answered {status} with {content_type:?}, passing it through");
+        let logged = unparsed_response(status, content_type.to_str().unwrap_or_default());
+        log_unparsed(&state, ip, &meta, &request, &logged, body.len(), started);
+        return Ok(raw_response(status, content_type.clone(), body));
+    }
 
     if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let logged = json!({
+            "error": {
+                "status": status.as_u16(),
+                "body": serde_json::from_str(&body).unwrap_or(Value::String(body.clone())),
+            }
+        });
+        log_unparsed(&state, ip, &meta, &request, &logged, body.len(), started);
         return Err(APIError {
-            code: response.status(),
-            body: Some("Upstream service error"),
+            code: status,
+            kind: ErrorKind::Upstream,
+            body: Some("Upstream service error".into()),
         });
     }
 
-    let content_type = response
-        .headers()
-        .get(header::CONTENT_TYPE)
-        .cloned()
-        .unwrap_or(header::HeaderValue::from_static("application/json"));
+    let declared_type = upstream_type.is_some();
+    let content_type = upstream_type.unwrap_or(HeaderValue::from_static("application/json"));
+    let forwarded = forwarded_headers(&state.config, response.headers());
 
     let is_streaming = request
         .get("stream")
         .and_then(Value::as_bool)
         .unwrap_or(false);
 
-    let ip = addr.ip();
-
     if is_streaming {
-        let mut stream = response.bytes_stream();
-        let mut buffer = Vec::new();
-        let mut usage_data = None;
-
-        while let Some(Ok(chunk)) = stream.next().await {
-            buffer.extend_from_slice(&chunk);
-
-            String::from_utf8_lossy(&chunk)
-                .lines()
-                .filter_map(|line| line.strip_prefix("data: "))
-                .filter(|&data| data != "[DONE]")
-                .filter_map(|data| serde_json::from_str::<Value>(data).ok())
-                .filter(|json| json.get("x_groq").and_then(|x| x.get("usage")).is_some())
-                .for_each(|json| usage_data = Some(json));
-        }
-
-        if let Some(final_response) = usage_data {
-            let tokens = extract_tokens(&final_response, true);
-            state
-                .log_request(&request, &final_response, ip, tokens)
-                .await;
-        }
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_SIZE);
+        let open_stream = state.active_streams.track();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let _open_stream = open_stream;
+            relay_stream(state, ip, meta, request, response, started, tx).await;
+        });
+
+        let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+            rx.recv()
+                .await
+                .map(|chunk| (Ok::<_, Infallible>(chunk), rx))
+        }));
 
-        Ok(Response::builder()
+        let mut builder = Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, content_type)
-            .body(Body::from(buffer))
-            .unwrap())
+            .extension(ServedModel(model));
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(forwarded);
+        }
+
+        Ok(builder.body(body).unwrap())
     } else {
-        let body = response.text().await.map_err(|e| {
+        let span = info_span!("upstream_body", elapsed_ms = Empty);
+        let body = timed(span, response.text()).await.map_err(|e| {
             error!("Failed to read response body: {}", e);
             APIError {
                 code: StatusCode::BAD_GATEWAY,
-                body: Some("Failed to read upstream response"),
+                kind: ErrorKind::Upstream,
+                body: Some("Failed to read upstream response".into()),
             }
         })?;
 
-        let json: Value = serde_json::from_str(&body).map_err(|e| {
-            error!("Failed to parse response JSON: {}", e);
-            APIError {
-                code: StatusCode::BAD_GATEWAY,
-                body: Some("Invalid response from upstream service"),
+        let json: Value = match serde_json::from_str(&body) {
+            Ok(json) => json,
+            Err(e) if !declared_type => {
+                warn!("Upstream sent an untyped body that is not JSON ({e}), passing it through");
+                let logged = unparsed_response(StatusCode::OK, "");
+                log_unparsed(&state, ip, &meta, &request, &logged, body.len(), started);
+                let text = HeaderValue::from_static("text/plain; charset=utf-8");
+                return Ok(raw_response(StatusCode::OK, text, body.into()));
             }
-        })?;
+            Err(e) => {
+                error!("Failed to parse response JSON: {}", e);
+                return Err(APIError {
+                    code: StatusCode::BAD_GATEWAY,
+                    kind: ErrorKind::Upstream,
+                    body: Some("Invalid response from upstream service".into()),
+                });
+            }
+        };
 
-        let tokens = extract_tokens(&json, false);
-        state.log_request(&request, &json, ip, tokens).await;
+        if let (Some(cache), Some(key)) = (&state.cache, &cache_key) {
+            cache.put(key, body.clone(), state.config.cache_ttl).await;
+        }
 
-        Ok(Response::builder()
+        if let (Some(cache), Some(key)) = (&state.cache, &idempotency_key) {
+            cache
+                .put(key, body.clone(), state.config.idempotency_ttl)
+                .await;
+        }
+
+        let counted = extract_tokens(&json, state.config.estimate_tokens);
+        let tokens = counted.map(|(t, _)| t);
+        state.log_request(LogEntry {
+            request: &request,
+            response: &json,
+            ip,
+            tokens,
+            token_source: counted.map(|(_, s)| s),
+            method: &meta.method,
+            path: &meta.path,
+            req_bytes: meta.bytes,
+            requested_model: meta.requested_model.as_deref(),
+            resolved_model: &model,
+            resp_bytes: body.len(),
+            latency: started.elapsed(),
+            finish_reason: finish_reason(&json),
+            completed: true,
+        });
+
+        let mut builder = Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, content_type)
+            .extension(ServedModel(model.clone()));
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(forwarded);
+        }
+        if state.config.content_filter_header && is_refusal(&json) {
+            builder = builder.header("X-Content-Filtered", "true");
+        }
+
+        let body = if query.pretty {
+            serde_json::to_string_pretty(&json).unwrap_or(body)
+        } else {
+            body
+        };
+
+        Ok(with_tokens(&state.config, builder, tokens)
             .body(Body::from(body))
             .unwrap())
     }
 }
+
+async fn relay_stream(
+    state: MetricsState,
+    ip: IpAddr,
+    meta: RequestMeta,
+    request: Value,
+    response: reqwest::Response,
+    started: Instant,
+    tx: mpsc::Sender<Bytes>,
+) {
+    let keepalive = state.config.stream_keepalive;
+    let max_stream = state.config.max_stream;
+    let cutoff = sleep(max_stream);
+    pin!(cutoff);
+
+    let mut stream = response.bytes_stream();
+    let mut resp_bytes = 0;
+    let mut usage = UsageAccumulator::for_request(&request);
+
+    let span = info_span!("stream_drain", model = %meta.resolved_model, elapsed_ms = Empty);
+    timed(span, async {
+        loop {
+            let idle = async {
+                if keepalive.is_zero() {
+                    future::pending().await
+                } else {
+                    sleep(keepalive).await
+                }
+            };
+
+            let next = select! {
+                next = stream.next() => next,
+                () = idle => {
+                    if tx.send(Bytes::from_static(KEEPALIVE)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                () = tx.closed() => break,
+                () = &mut cutoff, if !max_stream.is_zero() => {
+                    warn!(
+                        "Stream for {} exceeded {}s, cutting it off",
+                        meta.resolved_model,
+                        max_stream.as_secs()
+                    );
+                    break;
+                }
+            };
+
+            let Some(Ok(chunk)) = next else {
+                break;
+            };
+
+            resp_bytes += chunk.len();
+            usage.feed(&chunk);
+
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    })
+    .await;
+
+    if tx.is_closed() {
+        debug!("Client disconnected, dropped the upstream stream");
+    }
+
+    if let Some(summary) = usage.finish(state.config.estimate_tokens) {
+        // Headers went out before the first chunk, so the count trails the
+        // stream as an SSE comment, which clients skip.
+        if state.config.expose_tokens_header
+            && let Some((tokens, _)) = summary.tokens
+        {
+            let comment = format!(": x-tokens-used {tokens}\n\n");
+            let _ = tx.send(Bytes::from(comment)).await;
+        }
+
+        if !summary.completed {
+            warn!(
+                "Stream for {} ended early, last finish_reason {:?}",
+                meta.resolved_model, summary.finish_reason
+            );
+        }
+
+        state.log_request(LogEntry {
+            request: &request,
+            response: &summary.response,
+            ip,
+            tokens: summary.tokens.map(|(t, _)| t),
+            token_source: summary.tokens.map(|(_, s)| s),
+            method: &meta.method,
+            path: &meta.path,
+            req_bytes: meta.bytes,
+            requested_model: meta.requested_model.as_deref(),
+            resolved_model: &meta.resolved_model,
+            resp_bytes,
+            latency: started.elapsed(),
+            finish_reason: summary.finish_reason.as_deref(),
+            completed: summary.completed,
+        });
+    }
+}
+
+/// What gets logged for an upstream body that was passed through rather than
+/// parsed; non-2xx statuses are recorded as errors so sampling keeps them.
+fn unparsed_response(status: StatusCode, content_type: &str) -> Value {
+    let summary = json!({ "status": status.as_u16(), "content_type": content_type });
+    if status.is_success() {
+        json!({ "passthrough": summary })
+    } else {
+        json!({ "error": summary })
+    }
+}
+
+fn log_unparsed(
+    state: &MetricsState,
+    ip: IpAddr,
+    meta: &RequestMeta,
+    request: &Value,
+    response: &Value,
+    resp_bytes: usize,
+    started: Instant,
+) {
+    state.log_request(LogEntry {
+        request,
+        response,
+        ip,
+        tokens: None,
+        token_source: None,
+        method: &meta.method,
+        path: &meta.path,
+        req_bytes: meta.bytes,
+        requested_model: meta.requested_model.as_deref(),
+        resolved_model: &meta.resolved_model,
+        resp_bytes,
+        latency: started.elapsed(),
+        finish_reason: None,
+        completed: true,
+    });
+}
+
+/// Logs a completion served from the cache, returning its token count.
+fn log_cached(
+    state: &MetricsState,
+    ip: IpAddr,
+    meta: &RequestMeta,
+    request: &Value,
+    body: &str,
+) -> Option<i32> {
+    let json: Value = serde_json::from_str(body).unwrap_or_default();
+    let counted = extract_tokens(&json, state.config.estimate_tokens);
+    let tokens = counted.map(|(t, _)| t);
+
+    state.log_request(LogEntry {
+        request,
+        response: &json,
+        ip,
+        tokens,
+        token_source: counted.map(|(_, s)| s),
+        method: &meta.method,
+        path: &meta.path,
+        req_bytes: meta.bytes,
+        requested_model: meta.requested_model.as_deref(),
+        resolved_model: &meta.resolved_model,
+        resp_bytes: body.len(),
+        latency: Duration::ZERO,
+        finish_reason: finish_reason(&json),
+        completed: true,
+    });
+
+    tokens
+}
+
+pub async fn service_notice(State(state): State<MetricsState>, mut response: Response) -> Response {
+    let notice = &state.config.service_notice;
+    if !notice.is_empty()
+        && let Ok(value) = HeaderValue::from_str(notice)
+    {
+        response.headers_mut().insert("X-Service-Notice", value);
+    }
+
+    response
+}
+
+fn build_failed(e: reqwest::Error) -> APIError {
+    error!("Failed to build upstream request: {}", e);
+    APIError {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        kind: ErrorKind::Internal,
+        body: Some("Failed to build upstream request".into()),
+    }
+}
+
+/// Answers `?validate_only=true` with the normalized request.
+fn validated(headers: &HeaderMap, request: &Value, pretty: bool) -> Result<Response, APIError> {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if accept.contains("text/event-stream")
+        && !accept.contains("application/json")
+        && !accept.contains("*/*")
+    {
+        return Err(APIError {
+            code: StatusCode::BAD_REQUEST,
+            kind: ErrorKind::Client,
+            body: Some(
+                "`validate_only` returns JSON, but `Accept` only allows text/event-stream".into(),
+            ),
+        });
+    }
+
+    let body = if pretty {
+        serde_json::to_string_pretty(request)
+    } else {
+        serde_json::to_string(request)
+    }
+    .map_err(|_| APIError {
+        code: StatusCode::INTERNAL_SERVER_ERROR,
+        kind: ErrorKind::Internal,
+        body: Some("Failed to serialize request".into()),
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// The upstream response headers named in FORWARD_HEADERS.
+fn forwarded_headers(config: &Config, upstream: &HeaderMap) -> HeaderMap {
+    let mut forwarded = HeaderMap::new();
+    for name in &config.forward_headers {
+        for value in upstream.get_all(name) {
+            forwarded.append(name.clone(), value.clone());
+        }
+    }
+    forwarded
+}
+
+fn pretty(body: String) -> String {
+    serde_json::from_str::<Value>(&body)
+        .and_then(|json| serde_json::to_string_pretty(&json))
+        .unwrap_or(body)
+}
+
+fn cached_response(marker: &'static str, value: &'static str) -> Builder {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(marker, value)
+}
+
+fn is_json_or_stream(content_type: &HeaderValue) -> bool {
+    content_type
+        .to_str()
+        .is_ok_and(|value| value.contains("json") || value.starts_with("text/event-stream"))
+}
+
+/// An upstream body the proxy can't interpret, forwarded with its own status
+/// and content type rather than replaced by an error.
+fn raw_response(status: StatusCode, content_type: HeaderValue, body: Bytes) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Exponential backoff before the `failures`th retry or fallback, half of it
+/// random so clients that failed together don't retry together.
+fn retry_backoff(failures: u32) -> Duration {
+    let backoff = RETRY_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(RETRY_MAX_BACKOFF);
+    backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+}
+
+/// Retries for this request: `X-Max-Retries` if sent, UPSTREAM_RETRIES
+/// otherwise, never more than MAX_UPSTREAM_RETRIES.
+fn max_retries(headers: &HeaderMap, config: &Config) -> u32 {
+    headers
+        .get("x-max-retries")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(config.upstream_retries)
+        .min(config.max_upstream_retries)
+}
+
+fn with_tokens(config: &Config, builder: Builder, tokens: Option<i32>) -> Builder {
+    let Some(tokens) = tokens else {
+        return builder;
+    };
+
+    let builder = builder.extension(TokensUsed(tokens));
+    if config.expose_tokens_header {
+        builder.header("X-Tokens-Used", tokens)
+    } else {
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use axum::{
+        Json, Router,
+        middleware::{from_fn, from_fn_with_state},
+        routing::post,
+    };
+
+    use super::*;
+    use crate::{
+        delegates::circuit::CircuitState,
+        test_support::{
+            BROKEN_MODEL, Upstream, capture_logs, chunk, completion, db_state, event_stream,
+            eventually, logged_rows, ping, post_json, proxy, serve, state,
+        },
+    };
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn event_stream_accept_turns_streaming_on() {
+        let body = json!({ "stream": false });
+        assert!(wants_stream(&accept("text/event-stream"), &body));
+    }
+
+    #[test]
+    fn json_accept_keeps_the_body_stream_flag() {
+        let body = json!({ "stream": true });
+        assert!(wants_stream(&accept("application/json"), &body));
+        assert!(wants_stream(
+            &accept("application/json, text/plain, */*"),
+            &body
+        ));
+    }
+
+    #[test]
+    fn body_decides_without_accept() {
+        assert!(wants_stream(&HeaderMap::new(), &json!({ "stream": true })));
+        assert!(!wants_stream(&HeaderMap::new(), &json!({})));
+    }
+
+    /// Serves `validate_model` in front of a handler that reports what it
+    /// was handed: the normalized value, the logged size and whatever body
+    /// was left. `parsed` plays the part of a compat route's translation.
+    async fn behind_validate_model(
+        state: MetricsState,
+        parsed: Option<ParsedRequest>,
+    ) -> SocketAddr {
+        let handler = |Extension(meta): Extension<RequestMeta>,
+                       NormalizedRequest(json): NormalizedRequest,
+                       body: Bytes| async move {
+            Json(json!({ "json": json, "bytes": meta.bytes, "body_bytes": body.len() }))
+        };
+        let translate = move |mut req: Request, next: Next| {
+            if let Some(parsed) = parsed.clone() {
+                req.extensions_mut().insert(parsed);
+            }
+            next.run(req)
+        };
+        let router = Router::new()
+            .route("/chat/completions", post(handler))
+            .layer(from_fn_with_state(state.clone(), validate_model))
+            .layer(from_fn(translate))
+            .with_state(state);
+        serve(router).await
+    }
+
+    #[tokio::test]
+    async fn handlers_get_the_parsed_value_instead_of_a_body() {
+        let upstream = Upstream::start().await;
+        let addr = behind_validate_model(state(&upstream, &[]).await, None).await;
+
+        let body = json!({ "model": "llama", "messages": ping() });
+        let seen: Value = post_json(addr, "/chat/completions", body)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            seen["json"]["model"],
+            "meta-llama/llama-4-maverick-17b-128e-instruct"
+        );
+        assert_eq!(seen["body_bytes"], 0);
+    }
+
+    #[tokio::test]
+    async fn translated_requests_skip_the_body_parse() {
+        let upstream = Upstream::start().await;
+        let translated = ParsedRequest {
+            json: json!({ "model": "llama", "messages": ping() }),
+            bytes: 1234,
+        };
+        let addr = behind_validate_model(state(&upstream, &[]).await, Some(translated)).await;
+
+        // The body isn't JSON, so this only succeeds if it is never parsed.
+        let seen: Value = reqwest::Client::new()
+            .post(format!("http://{addr}/chat/completions"))
+            .body("not json")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            seen["json"]["model"],
+            "meta-llama/llama-4-maverick-17b-128e-instruct"
+        );
+        assert_eq!(seen["bytes"], 1234);
+    }
+
+    #[test]
+    fn only_allowlisted_upstream_headers_are_forwarded() {
+        let config =
+            Config::example(&[("FORWARD_HEADERS", "x-request-id,x-ratelimit-remaining")]).unwrap();
+        let mut upstream = HeaderMap::new();
+        upstream.insert("x-request-id", HeaderValue::from_static("req_1"));
+        upstream.append("x-ratelimit-remaining", HeaderValue::from_static("9"));
+        upstream.append("x-ratelimit-remaining", HeaderValue::from_static("99"));
+        upstream.insert("openai-processing-ms", HeaderValue::from_static("42"));
+        upstream.insert(header::SET_COOKIE, HeaderValue::from_static("session=1"));
+
+        let forwarded = forwarded_headers(&config, &upstream);
+        assert_eq!(forwarded.len(), 3);
+        assert_eq!(forwarded["x-request-id"], "req_1");
+        let remaining: Vec<_> = forwarded.get_all("x-ratelimit-remaining").iter().collect();
+        assert_eq!(remaining, ["9", "99"]);
+
+        let unset = Config::example(&[]).unwrap();
+        assert!(forwarded_headers(&unset, &upstream).is_empty());
+    }
+
+    #[test]
+    fn unparsed_errors_survive_sampling() {
+        let failed = unparsed_response(StatusCode::BAD_GATEWAY, "text/html");
+        assert_eq!(failed["error"]["status"], 502);
+
+        let passed = unparsed_response(StatusCode::OK, "text/plain");
+        assert!(passed.get("error").is_none());
+        assert_eq!(passed["passthrough"]["content_type"], "text/plain");
+    }
+
+    #[tokio::test]
+    async fn non_json_error_pages_become_api_errors() {
+        let upstream = Upstream::responding(|body| async move {
+            let (status, content_type, page) = match body["messages"][0]["content"].as_str() {
+                Some("ping") => (
+                    StatusCode::BAD_GATEWAY,
+                    "text/html",
+                    "<h1>502 Bad Gateway</h1>",
+                ),
+                _ => (StatusCode::OK, "text/plain", "plain pong"),
+            };
+            (status, [(header::CONTENT_TYPE, content_type)], page).into_response()
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["message"], "Upstream service error");
+        assert_eq!(error["error"]["type"], "api_error");
+
+        let plain = json!({ "messages": [{ "role": "user", "content": "hi" }] });
+        let response = post_json(proxy, "/chat/completions", plain).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(response.text().await.unwrap(), "plain pong");
+    }
+
+    #[tokio::test]
+    async fn saturated_upstream_answers_503_after_the_queue_wait() {
+        let upstream = Upstream::start().await;
+        let overrides = [("MAX_CONCURRENT_UPSTREAM", "1"), ("QUEUE_WAIT_MS", "50")];
+        let state = state(&upstream, &overrides).await;
+        let permits = state.upstream_permits.clone();
+        let proxy = proxy(state).await;
+        let body = json!({ "messages": ping() });
+
+        let held = permits.acquire().await.unwrap();
+        let started = Instant::now();
+        let response = post_json(proxy, "/chat/completions", body.clone()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(upstream.requests().is_empty());
+
+        drop(held);
+        let response = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn over_limit_requests_wait_for_a_permit() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (counter, max) = (in_flight.clone(), peak.clone());
+        let upstream = Upstream::responding(move |body| {
+            let (counter, max) = (counter.clone(), max.clone());
+            async move {
+                max.fetch_max(counter.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                sleep(Duration::from_millis(100)).await;
+                counter.fetch_sub(1, Ordering::SeqCst);
+                Json(completion(&body["model"], "pong")).into_response()
+            }
+        })
+        .await;
+        let overrides = [("MAX_CONCURRENT_UPSTREAM", "1"), ("QUEUE_WAIT_MS", "2000")];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+        let body = json!({ "messages": ping() });
+
+        let (first, second) = tokio::join!(
+            post_json(proxy, "/chat/completions", body.clone()),
+            post_json(proxy, "/chat/completions", body),
+        );
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_upstream_opens_the_circuit() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("CIRCUIT_FAILURE_THRESHOLD", "2")]).await).await;
+
+        for _ in 0..2 {
+            let body = json!({ "model": BROKEN_MODEL, "messages": ping() });
+            let response = post_json(proxy, "/chat/completions", body).await;
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(
+            error["error"]["message"],
+            "Upstream service is temporarily unavailable"
+        );
+        assert_eq!(upstream.requests().len(), 2);
+    }
+
+    fn retry_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-max-retries", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn x_max_retries_overrides_the_default_up_to_the_server_max() {
+        let config =
+            Config::example(&[("UPSTREAM_RETRIES", "2"), ("MAX_UPSTREAM_RETRIES", "3")]).unwrap();
+
+        assert_eq!(max_retries(&HeaderMap::new(), &config), 2);
+        assert_eq!(max_retries(&retry_headers("0"), &config), 0);
+        assert_eq!(max_retries(&retry_headers(" 1 "), &config), 1);
+        assert_eq!(max_retries(&retry_headers("10"), &config), 3);
+        assert_eq!(max_retries(&retry_headers("-1"), &config), 2);
+        assert_eq!(max_retries(&retry_headers("lots"), &config), 2);
+    }
+
+    #[test]
+    fn retry_backoff_grows_with_jitter_up_to_the_cap() {
+        for failures in 1..=3 {
+            let full = RETRY_BACKOFF * 2u32.pow(failures - 1);
+            let backoff = retry_backoff(failures);
+            assert!(full / 2 <= backoff && backoff <= full, "{backoff:?}");
+        }
+        assert!(retry_backoff(u32::MAX) <= RETRY_MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn retries_follow_the_header_and_count_once_toward_the_circuit() {
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("UPSTREAM_RETRIES", "2"),
+            ("MAX_UPSTREAM_RETRIES", "3"),
+            ("CIRCUIT_FAILURE_THRESHOLD", "3"),
+        ];
+        let state = state(&upstream, &overrides).await;
+        let proxy = proxy(state.clone()).await;
+        let broken = |retries: &str| {
+            reqwest::Client::new()
+                .post(format!("http://{proxy}/chat/completions"))
+                .header("X-Max-Retries", retries)
+                .json(&json!({ "model": BROKEN_MODEL, "messages": ping() }))
+                .send()
+        };
+
+        let started = Instant::now();
+        broken("0").await.unwrap();
+        assert_eq!(upstream.requests().len(), 1);
+        broken("10").await.unwrap();
+        assert_eq!(upstream.requests().len(), 5);
+        assert!(started.elapsed() >= Duration::from_millis(350));
+
+        assert_eq!(state.circuit.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn failing_models_are_served_by_their_fallback() {
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("UPSTREAM_RETRIES", "1"),
+            (
+                "MODEL_FALLBACKS",
+                r#"{"openai/gpt-oss-20b":["openai/gpt-oss-120b"]}"#,
+            ),
+        ];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let body = json!({ "model": BROKEN_MODEL, "messages": ping() });
+        let response = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let served: Value = response.json().await.unwrap();
+        assert_eq!(served["model"], "openai/gpt-oss-120b");
+
+        // The primary spends its retry before the fallback is tried.
+        assert_eq!(
+            upstream.models(),
+            [BROKEN_MODEL, BROKEN_MODEL, "openai/gpt-oss-120b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn preflight_skips_body_validation() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let response = reqwest::Client::new()
+            .request(Method::OPTIONS, format!("http://{proxy}/chat/completions"))
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn logs_request_and_response_sizes() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+        let body = json!({ "messages": ping() });
+
+        let response = post_json(proxy, "/chat/completions", body.clone()).await;
+        let response = response.bytes().await.unwrap();
+
+        let rows = logged_rows(&state, 1).await;
+        let req_bytes: i32 = rows[0].get("req_bytes");
+        let resp_bytes: i32 = rows[0].get("resp_bytes");
+        assert_eq!(req_bytes as usize, body.to_string().len());
+        assert_eq!(resp_bytes as usize, response.len());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn logs_the_requested_and_resolved_models() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let body = json!({ "model": "gpt-4o", "messages": ping() });
+        post_json(proxy, "/chat/completions", body).await;
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+
+        let rows = logged_rows(&state, 2).await;
+        let models = |row: &tokio_postgres::Row| {
+            (
+                row.get::<_, Option<String>>("requested_model"),
+                row.get::<_, String>("resolved_model"),
+            )
+        };
+        assert_eq!(
+            models(&rows[0]),
+            (Some("gpt-4o".to_string()), "qwen/qwen3-32b".to_string())
+        );
+        assert_eq!(models(&rows[1]), (None, "qwen/qwen3-32b".to_string()));
+    }
+
+    #[tokio::test]
+    async fn every_validation_problem_is_reported() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("STRICT_MODEL", "true")]).await).await;
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": ping(),
+            "tools": [{ "type": "retrieval" }],
+            "stop": 42,
+        });
+
+        let response = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: Value = response.json().await.unwrap();
+        let details: Vec<&str> = error["error"]["details"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d.as_str().unwrap())
+            .collect();
+        assert_eq!(details.len(), 3, "{details:?}");
+        assert!(details[0].starts_with("Each tool must have"));
+        assert!(details[1].starts_with("`stop` must be"));
+        assert!(details[2].starts_with("Unknown model"));
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn too_many_messages_is_a_400() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("MAX_MESSAGES", "1")]).await).await;
+        let messages = json!([
+            { "role": "user", "content": "one" },
+            { "role": "user", "content": "two" },
+        ]);
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": messages })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(
+            error["error"]["message"],
+            "Too many messages, at most 1 are allowed"
+        );
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn cache_hits_count_toward_usage() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("CACHE_TTL_SECS", "60")]).await;
+        let proxy = proxy(state.clone()).await;
+        let body = json!({ "messages": ping(), "temperature": 0 });
+
+        post_json(proxy, "/chat/completions", body.clone()).await;
+        let hit = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+        assert_eq!(hit.headers()["X-Tokens-Used"], "4");
+
+        logged_rows(&state, 2).await;
+        let usage = state.usage_for_ip("127.0.0.1".parse().unwrap()).await;
+        assert_eq!(usage, (2, 8));
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn logs_whether_logprobs_were_requested() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let body = json!({ "messages": ping(), "logprobs": true, "top_logprobs": 2 });
+        post_json(proxy, "/chat/completions", body).await;
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+
+        let rows = logged_rows(&state, 2).await;
+        assert!(rows[0].get::<_, bool>("used_logprobs"));
+        assert!(!rows[1].get::<_, bool>("used_logprobs"));
+    }
+
+    #[tokio::test]
+    async fn estimates_tokens_when_upstream_omits_usage() {
+        let upstream = Upstream::responding(|body| async move {
+            let mut response = completion(&body["model"], "eight ch");
+            response.as_object_mut().unwrap().remove("usage");
+            Json(response).into_response()
+        })
+        .await;
+        let body = json!({ "messages": ping() });
+
+        let estimating = proxy(state(&upstream, &[("ESTIMATE_TOKENS", "true")]).await).await;
+        let response = post_json(estimating, "/chat/completions", body.clone()).await;
+        assert_eq!(response.headers()["X-Tokens-Used"], "2");
+
+        let counting = proxy(state(&upstream, &[("ESTIMATE_TOKENS", "false")]).await).await;
+        let response = post_json(counting, "/chat/completions", body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("X-Tokens-Used").is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_streams_get_keepalive_comments() {
+        let upstream = Upstream::responding(|body| async move {
+            event_stream(vec![
+                (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                (Duration::from_millis(1500), "[DONE]".to_string()),
+            ])
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[("STREAM_KEEPALIVE_SECS", "1")]).await).await;
+
+        let body = json!({ "messages": ping(), "stream": true });
+        let response = post_json(proxy, "/chat/completions", body).await;
+        let text = response.text().await.unwrap();
+
+        let keepalive = text.find(": keepalive").expect("no keepalive comment sent");
+        assert!(text.find("pong").unwrap() < keepalive);
+        assert!(keepalive < text.find("[DONE]").unwrap());
+    }
+
+    #[tokio::test]
+    async fn streams_end_with_a_token_count_comment() {
+        let upstream = Upstream::responding(|body| async move {
+            let mut last = chunk(&body["model"], "");
+            last["usage"] = json!({ "total_tokens": 7 });
+            event_stream(vec![
+                (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                (Duration::ZERO, last.to_string()),
+                (Duration::ZERO, "[DONE]".to_string()),
+            ])
+        })
+        .await;
+        let body = json!({ "messages": ping(), "stream": true });
+
+        let exposing = proxy(state(&upstream, &[]).await).await;
+        let response = post_json(exposing, "/chat/completions", body.clone()).await;
+        assert!(response.headers().get("X-Tokens-Used").is_none());
+        let text = response.text().await.unwrap();
+        assert!(
+            text.ends_with("data: [DONE]\n\n: x-tokens-used 7\n\n"),
+            "{text}"
+        );
+
+        let overrides = [("EXPOSE_TOKENS_HEADER", "false")];
+        let hiding = proxy(state(&upstream, &overrides).await).await;
+        let response = post_json(hiding, "/chat/completions", body).await;
+        let text = response.text().await.unwrap();
+        assert!(!text.contains("x-tokens-used"));
+    }
+
+    #[tokio::test]
+    async fn pretty_only_reformats_json_responses() {
+        let upstream = Upstream::responding(|body| async move {
+            if body["stream"] == true {
+                return event_stream(vec![
+                    (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                    (Duration::ZERO, "[DONE]".to_string()),
+                ]);
+            }
+            Json(completion(&body["model"], "pong")).into_response()
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let body = json!({ "messages": ping() });
+        let compact = post_json(proxy, "/chat/completions", body.clone()).await;
+        assert_eq!(compact.headers()["X-Tokens-Used"], "4");
+        let compact = compact.text().await.unwrap();
+        assert!(!compact.contains('\n'), "{compact}");
+
+        let pretty = post_json(proxy, "/chat/completions?pretty=true", body).await;
+        assert_eq!(pretty.headers()["X-Tokens-Used"], "4");
+        let pretty = pretty.text().await.unwrap();
+        assert!(pretty.contains("\n  \"choices\": ["), "{pretty}");
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            serde_json::from_str::<Value>(&compact).unwrap()
+        );
+
+        let stream = json!({ "messages": ping(), "stream": true });
+        let plain = post_json(proxy, "/chat/completions", stream.clone()).await;
+        let pretty = post_json(proxy, "/chat/completions?pretty=true", stream).await;
+        assert_eq!(pretty.text().await.unwrap(), plain.text().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn validate_only_wins_over_streaming_unless_accept_rules_out_json() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let path = "/chat/completions?validate_only=true";
+
+        let cases = [
+            (false, None, StatusCode::OK),
+            (true, None, StatusCode::OK),
+            (false, Some("text/event-stream"), StatusCode::BAD_REQUEST),
+            (true, Some("text/event-stream"), StatusCode::BAD_REQUEST),
+            (
+                true,
+                Some("text/event-stream, application/json"),
+                StatusCode::OK,
+            ),
+            (true, Some("text/event-stream, */*"), StatusCode::OK),
+            (true, Some("application/json"), StatusCode::OK),
+        ];
+        for (stream, accept, expected) in cases {
+            let mut request = reqwest::Client::new()
+                .post(format!("http://{proxy}{path}"))
+                .json(&json!({ "model": "llama", "messages": ping(), "stream": stream }));
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            let response = request.send().await.unwrap();
+
+            let case = format!("stream: {stream}, accept: {accept:?}");
+            assert_eq!(response.status(), expected, "{case}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/json",
+                "{case}"
+            );
+            let body: Value = response.json().await.unwrap();
+            if expected == StatusCode::OK {
+                assert_eq!(
+                    body["model"], "meta-llama/llama-4-maverick-17b-128e-instruct",
+                    "{case}"
+                );
+                assert_eq!(body["stream"], stream, "{case}");
+            } else {
+                assert!(body["error"]["message"].is_string(), "{case}");
+            }
+        }
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn finished_streams_leave_the_active_stream_gauge() {
+        let upstream = Upstream::responding(|body| async move {
+            event_stream(vec![
+                (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                (Duration::from_millis(200), "[DONE]".to_string()),
+            ])
+        })
+        .await;
+        let state = state(&upstream, &[]).await;
+        let streams = state.active_streams.clone();
+        let proxy = proxy(state).await;
+
+        let body = json!({ "messages": ping(), "stream": true });
+        let mut response = post_json(proxy, "/chat/completions", body).await;
+        response.chunk().await.unwrap();
+        assert_eq!(streams.get(), 1);
+
+        while response.chunk().await.unwrap().is_some() {}
+        eventually(|| async { streams.get() == 0 }).await;
+    }
+
+    #[tokio::test]
+    async fn extra_upstream_headers_reach_the_upstream() {
+        let upstream = Upstream::start().await;
+        let overrides = [(
+            "EXTRA_UPSTREAM_HEADERS",
+            r#"{"OpenAI-Beta":"assistants=v2"}"#,
+        )];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(
+            upstream.requests()[0].headers["openai-beta"],
+            "assistants=v2"
+        );
+    }
+
+    #[tokio::test]
+    async fn allowlisted_upstream_headers_reach_the_client() {
+        let upstream = Upstream::responding(|body| async move {
+            let mut response = if body["stream"] == true {
+                event_stream(vec![
+                    (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                    (Duration::ZERO, "[DONE]".to_string()),
+                ])
+            } else {
+                Json(completion(&body["model"], "pong")).into_response()
+            };
+            let headers = response.headers_mut();
+            headers.insert("x-request-id", HeaderValue::from_static("req_1"));
+            headers.insert("openai-processing-ms", HeaderValue::from_static("42"));
+            response
+        })
+        .await;
+        let overrides = [("FORWARD_HEADERS", "x-request-id")];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        for stream in [false, true] {
+            let body = json!({ "messages": ping(), "stream": stream });
+            let response = post_json(proxy, "/chat/completions", body).await;
+            assert_eq!(
+                response.headers()["x-request-id"],
+                "req_1",
+                "stream: {stream}"
+            );
+            assert!(
+                !response.headers().contains_key("openai-processing-ms"),
+                "stream: {stream}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_key_degrades_instead_of_calling_upstream() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("KEY", "")]).await).await;
+
+        let ready = reqwest::get(format!("http://{proxy}/readyz"))
+            .await
+            .unwrap();
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["message"], "Service not configured");
+        assert!(upstream.requests().is_empty());
+    }
+
+    fn idempotent(proxy: SocketAddr, key: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .post(format!("http://{proxy}/chat/completions"))
+            .header("Idempotency-Key", key)
+            .json(&json!({ "messages": ping(), "temperature": 0.7 }))
+    }
+
+    #[tokio::test]
+    async fn repeated_idempotency_keys_replay_the_first_response() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("IDEMPOTENCY_TTL_SECS", "60")]).await).await;
+
+        let first = idempotent(proxy, "retry-1").send().await.unwrap();
+        assert!(first.headers().get("Idempotent-Replayed").is_none());
+        let first: Value = first.json().await.unwrap();
+
+        let replay = idempotent(proxy, "retry-1").send().await.unwrap();
+        assert_eq!(replay.headers()["Idempotent-Replayed"], "true");
+        assert_eq!(replay.json::<Value>().await.unwrap(), first);
+        assert_eq!(upstream.requests().len(), 1);
+
+        idempotent(proxy, "retry-2").send().await.unwrap();
+        assert_eq!(upstream.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn idempotent_replays_are_not_charged_again() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("IDEMPOTENCY_TTL_SECS", "60")]).await;
+        let proxy = proxy(state.clone()).await;
+
+        idempotent(proxy, "retry-1").send().await.unwrap();
+        let replay = idempotent(proxy, "retry-1").send().await.unwrap();
+        assert_eq!(replay.headers()["Idempotent-Replayed"], "true");
+
+        logged_rows(&state, 1).await;
+        let usage = state.usage_for_ip("127.0.0.1".parse().unwrap()).await;
+        assert_eq!(usage, (1, 4));
+    }
+
+    /// Answers after 300ms for the default model and at once for the rest.
+    async fn slow_default_model() -> Upstream {
+        Upstream::responding(|body| async move {
+            if body["model"] == "qwen/qwen3-32b" {
+                sleep(Duration::from_millis(300)).await;
+            }
+            Json(completion(&body["model"], "pong")).into_response()
+        })
+        .await
+    }
+
+    async fn served_model(proxy: SocketAddr) -> Value {
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        response.json::<Value>().await.unwrap()["model"].clone()
+    }
+
+    #[tokio::test]
+    async fn speed_budget_only_applies_with_a_fallback() {
+        let upstream = slow_default_model().await;
+        let fallback = ("SPEED_FALLBACK_MODEL", "openai/gpt-oss-120b");
+
+        let disabled = [("SPEED_BUDGET_MS", "0"), fallback];
+        let unbudgeted = proxy(state(&upstream, &disabled).await).await;
+        assert_eq!(served_model(unbudgeted).await, "qwen/qwen3-32b");
+
+        let no_fallback = [("SPEED_BUDGET_MS", "50"), ("SPEED_FALLBACK_MODEL", "")];
+        let unrouted = proxy(state(&upstream, &no_fallback).await).await;
+        assert_eq!(served_model(unrouted).await, "qwen/qwen3-32b");
+
+        assert_eq!(upstream.models(), ["qwen/qwen3-32b", "qwen/qwen3-32b"]);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_speed_budget_move_to_the_fallback() {
+        let upstream = slow_default_model().await;
+        let overrides = [
+            ("SPEED_BUDGET_MS", "50"),
+            ("SPEED_FALLBACK_MODEL", "openai/gpt-oss-120b"),
+        ];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let started = Instant::now();
+        assert_eq!(served_model(proxy).await, "openai/gpt-oss-120b");
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(upstream.models(), ["qwen/qwen3-32b", "openai/gpt-oss-120b"]);
+    }
+
+    #[tokio::test]
+    async fn missing_the_speed_budget_counts_as_a_circuit_failure() {
+        let upstream = slow_default_model().await;
+        let overrides = [
+            ("SPEED_BUDGET_MS", "50"),
+            ("SPEED_FALLBACK_MODEL", "openai/gpt-oss-120b"),
+            ("CIRCUIT_FAILURE_THRESHOLD", "1"),
+        ];
+        let state = state(&upstream, &overrides).await;
+        let proxy = proxy(state.clone()).await;
+
+        assert_eq!(served_model(proxy).await, "openai/gpt-oss-120b");
+        assert_eq!(state.circuit.state(), CircuitState::Open);
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(upstream.requests().len(), 2);
+    }
+
+    /// Streams `count` chunks 100ms apart, counting how many were produced.
+    async fn trickling_upstream(count: usize, produced: Arc<AtomicUsize>) -> Upstream {
+        Upstream::responding(move |body| {
+            let produced = produced.clone();
+            async move {
+                let chunks = (0..count).map(|i| {
+                    let delay = Duration::from_millis(if i == 0 { 0 } else { 100 });
+                    (delay, chunk(&body["model"], &format!("c{i} ")).to_string())
+                });
+                let response = event_stream(chunks.collect());
+                let (parts, body) = response.into_parts();
+                let counted = body.into_data_stream().inspect(move |_| {
+                    produced.fetch_add(1, Ordering::SeqCst);
+                });
+                Response::from_parts(parts, Body::from_stream(counted))
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn max_stream_secs_cuts_long_streams_off() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let upstream = trickling_upstream(30, produced).await;
+        let proxy = proxy(state(&upstream, &[("MAX_STREAM_SECS", "1")]).await).await;
+
+        let started = Instant::now();
+        let body = json!({ "messages": ping(), "stream": true });
+        let text = post_json(proxy, "/chat/completions", body)
+            .await
+            .text()
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(text.contains("c0 "));
+        assert!(!text.contains("c29 "));
+    }
+
+    #[tokio::test]
+    async fn dropped_clients_stop_the_upstream_stream() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let upstream = trickling_upstream(50, produced.clone()).await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let body = json!({ "messages": ping(), "stream": true });
+        let mut response = post_json(proxy, "/chat/completions", body).await;
+        response.chunk().await.unwrap();
+        drop(response);
+
+        sleep(Duration::from_millis(500)).await;
+        let after_drop = produced.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(produced.load(Ordering::SeqCst), after_drop);
+        assert!(after_drop < 50);
+    }
+
+    #[tokio::test]
+    async fn service_notice_header_only_appears_when_set() {
+        let upstream = Upstream::start().await;
+        let body = json!({ "messages": ping() });
+
+        let quiet = proxy(state(&upstream, &[]).await).await;
+        let response = post_json(quiet, "/chat/completions", body.clone()).await;
+        assert!(response.headers().get("X-Service-Notice").is_none());
+
+        let notice = "qwen3 retires on Friday";
+        let noisy = proxy(state(&upstream, &[("SERVICE_NOTICE", notice)]).await).await;
+        let response = post_json(noisy, "/chat/completions", body).await;
+        assert_eq!(response.headers()["X-Service-Notice"], notice);
+
+        let index = reqwest::get(format!("http://{noisy}/")).await.unwrap();
+        assert!(index.text().await.unwrap().contains(notice));
+    }
+
+    #[tokio::test]
+    async fn timing_spans_wrap_each_section() {
+        let logs = capture_logs();
+        let upstream = Upstream::responding(|body| async move {
+            if body["stream"] == true {
+                event_stream(vec![
+                    (Duration::ZERO, chunk(&body["model"], "pong").to_string()),
+                    (Duration::ZERO, "[DONE]".to_string()),
+                ])
+            } else {
+                Json(completion(&body["model"], "pong")).into_response()
+            }
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        let streamed = json!({ "messages": ping(), "stream": true });
+        let response = post_json(proxy, "/chat/completions", streamed).await;
+        response.text().await.unwrap();
+
+        let closed = |span: &str| logs.lines_with(&[&format!("{span}{{"), "elapsed_ms=", "close"]);
+        assert_eq!(closed("upstream_send").len(), 2, "{}", logs.contents());
+        assert_eq!(closed("upstream_body").len(), 1, "{}", logs.contents());
+        eventually(|| async { closed("stream_drain").len() == 1 }).await;
+        assert!(closed("upstream_send")[0].contains("model=qwen/qwen3-32b"));
+    }
+
+    #[tokio::test]
+    async fn content_filter_header_marks_refusals_when_enabled() {
+        let upstream = Upstream::responding(|body| async move {
+            let content = body["messages"][0]["content"].as_str().unwrap_or_default();
+            let reply = match content {
+                "ping" => "pong",
+                _ => "I can't help with that.",
+            };
+            Json(completion(&body["model"], reply)).into_response()
+        })
+        .await;
+        let refused = json!({ "messages": [{ "role": "user", "content": "pick a lock" }] });
+
+        let enabled = [("CONTENT_FILTER_HEADER", "true")];
+        let filtering = proxy(state(&upstream, &enabled).await).await;
+        let response = post_json(filtering, "/chat/completions", refused.clone()).await;
+        assert_eq!(response.headers()["X-Content-Filtered"], "true");
+        let response = post_json(
+            filtering,
+            "/chat/completions",
+            json!({ "messages": ping() }),
+        )
+        .await;
+        assert!(response.headers().get("X-Content-Filtered").is_none());
+
+        let quiet = proxy(state(&upstream, &[]).await).await;
+        let response = post_json(quiet, "/chat/completions", refused).await;
+        assert!(response.headers().get("X-Content-Filtered").is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn content_filtered_responses_are_logged_as_refused() {
+        let upstream = Upstream::responding(|body| async move {
+            let mut filtered = completion(&body["model"], "");
+            if body["messages"][0]["content"] != "ping" {
+                filtered["choices"][0]["finish_reason"] = json!("content_filter");
+            }
+            Json(filtered).into_response()
+        })
+        .await;
+        let state = db_state(&upstream, &[("CONTENT_FILTER_HEADER", "true")]).await;
+        let proxy = proxy(state.clone()).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        logged_rows(&state, 1).await;
+        let filtered = json!({ "messages": [{ "role": "user", "content": "pick a lock" }] });
+        let response = post_json(proxy, "/chat/completions", filtered).await;
+        assert_eq!(response.headers()["X-Content-Filtered"], "true");
+
+        let rows = logged_rows(&state, 2).await;
+        assert!(!rows[0].get::<_, bool>("refused"));
+        assert!(rows[1].get::<_, bool>("refused"));
+        assert_eq!(
+            rows[1].get::<_, Option<&str>>("finish_reason"),
+            Some("content_filter")
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn forwards_the_user_and_logs_it() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let tagged = json!({ "messages": ping(), "user": "student-7f3a" });
+        post_json(proxy, "/chat/completions", tagged).await;
+        logged_rows(&state, 1).await;
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+
+        let sent = upstream.requests();
+        assert_eq!(sent[0].body["user"], "student-7f3a");
+        assert!(sent[1].body.get("user").is_none());
+
+        let rows = logged_rows(&state, 2).await;
+        assert_eq!(
+            rows[0].get::<_, Option<&str>>("user_tag"),
+            Some("student-7f3a")
+        );
+        assert_eq!(rows[1].get::<_, Option<&str>>("user_tag"), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn passes_the_seed_through_and_logs_it() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let seeded = json!({ "messages": ping(), "seed": 42 });
+        post_json(proxy, "/chat/completions", seeded).await;
+        logged_rows(&state, 1).await;
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+
+        let sent = upstream.requests();
+        assert_eq!(sent[0].body["seed"], 42);
+        assert!(sent[1].body.get("seed").is_none());
+
+        let rows = logged_rows(&state, 2).await;
+        assert_eq!(rows[0].get::<_, Option<i64>>("seed"), Some(42));
+        assert_eq!(rows[1].get::<_, Option<i64>>("seed"), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn forwards_the_reasoning_effort_and_logs_it() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let requests = [
+            json!({ "messages": ping(), "reasoning_effort": "high" }),
+            json!({ "messages": ping(), "reasoning_effort": "extreme" }),
+            json!({ "messages": ping() }),
+        ];
+        for (logged, request) in requests.into_iter().enumerate() {
+            post_json(proxy, "/chat/completions", request).await;
+            logged_rows(&state, logged + 1).await;
+        }
+
+        let sent = upstream.requests();
+        assert_eq!(sent[0].body["reasoning_effort"], "high");
+        assert!(sent[1].body.get("reasoning_effort").is_none());
+        assert!(sent[2].body.get("reasoning_effort").is_none());
+
+        let rows = logged_rows(&state, 3).await;
+        let efforts: Vec<Option<&str>> = rows.iter().map(|r| r.get("reasoning_effort")).collect();
+        assert_eq!(efforts, [Some("high"), None, None]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn stores_metadata_and_rejects_oversized_metadata() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("MAX_METADATA_BYTES", "64")]).await;
+        let proxy = proxy(state.clone()).await;
+
+        let metadata = json!({ "session": "s-81", "tags": ["hw", 3] });
+        let tagged = json!({ "messages": ping(), "metadata": metadata });
+        post_json(proxy, "/chat/completions", tagged).await;
+
+        let oversized = json!({ "messages": ping(), "metadata": { "notes": "x".repeat(64) } });
+        let response = post_json(proxy, "/chat/completions", oversized).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: Value = response.json().await.unwrap();
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("`metadata` is too large, at most 64 bytes are allowed")
+        );
+
+        let sent = upstream.requests();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body["metadata"], metadata);
+
+        let rows = logged_rows(&state, 1).await;
+        assert_eq!(rows[0].get::<_, Option<Value>>("metadata"), Some(metadata));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn flags_responses_from_a_substituted_model() {
+        let upstream = Upstream::responding(|body| async move {
+            let model = match body["model"].as_str() {
+                Some("openai/gpt-oss-120b") => json!("openai/gpt-oss-120b-fast"),
+                _ => body["model"].clone(),
+            };
+            Json(completion(&model, "pong")).into_response()
+        })
+        .await;
+        let state = db_state(&upstream, &[]).await;
+        let proxy = proxy(state.clone()).await;
+
+        post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        logged_rows(&state, 1).await;
+        let substituted = json!({ "model": "openai/gpt-oss-120b", "messages": ping() });
+        post_json(proxy, "/chat/completions", substituted).await;
+
+        let rows = logged_rows(&state, 2).await;
+        assert!(!rows[0].get::<_, bool>("model_mismatch"));
+        assert!(rows[1].get::<_, bool>("model_mismatch"));
+    }
+}
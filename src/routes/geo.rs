@@ -0,0 +1,175 @@
+use std::net::IpAddr;
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use maxminddb::{Reader, geoip2};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    delegates::{
+        client_ip::ClientIp,
+        error::{APIError, ErrorKind},
+    },
+    metrics::database::MetricsState,
+};
+
+pub struct GeoBlocker {
+    reader: Reader<Vec<u8>>,
+    allowed: Vec<String>,
+    blocked: Vec<String>,
+}
+
+impl GeoBlocker {
+    /// Loads the MMDB named by GEOIP_DB_PATH. Geoblocking is skipped
+    /// entirely when no path or country list is set or the file can't be read.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.geoip_db_path.is_empty() {
+            return None;
+        }
+        if config.geo_allowed_countries.is_empty() && config.geo_blocked_countries.is_empty() {
+            warn!("GEOIP_DB_PATH is set without any country list, geoblocking is disabled");
+            return None;
+        }
+
+        match Reader::open_readfile(&config.geoip_db_path) {
+            Ok(reader) => {
+                info!("Geoblocking enabled using {}", config.geoip_db_path);
+                Some(Self {
+                    reader,
+                    allowed: config.geo_allowed_countries.clone(),
+                    blocked: config.geo_blocked_countries.clone(),
+                })
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to open GeoIP database {}, geoblocking is disabled: {}",
+                    config.geoip_db_path, e
+                );
+                None
+            }
+        }
+    }
+
+    /// IPs the database can't place in a country are let through.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let Some(country) = self
+            .reader
+            .lookup::<geoip2::Country>(ip)
+            .ok()
+            .and_then(|record| record.country?.iso_code)
+        else {
+            return true;
+        };
+
+        let listed = |list: &[String]| list.iter().any(|c| c.eq_ignore_ascii_case(country));
+        !listed(&self.blocked) && (self.allowed.is_empty() || listed(&self.allowed))
+    }
+}
+
+pub async fn enforce_geoblock(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    if let Some(geo) = &state.geo
+        && !geo.allows(ip)
+    {
+        return Err(APIError {
+            code: StatusCode::FORBIDDEN,
+            kind: ErrorKind::Client,
+            body: Some("This service is not available in your region".into()),
+        });
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{Upstream, geoip_db, ping, proxy, state};
+
+    /// 1.0.0.0/8 is in the US and 2.0.0.0/8 in North Korea.
+    fn blocker(lists: &[(&str, &str)]) -> Option<GeoBlocker> {
+        let path = geoip_db(&[(1, "US"), (2, "KP")]);
+        let mut vars = vec![("GEOIP_DB_PATH", path.as_str())];
+        vars.extend_from_slice(lists);
+        GeoBlocker::from_config(&Config::example(&vars).unwrap())
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn allowlist_admits_only_listed_countries() {
+        let geo = blocker(&[("GEO_ALLOWED_COUNTRIES", "us,ca")]).unwrap();
+        assert!(geo.allows(ip("1.2.3.4")));
+        assert!(!geo.allows(ip("2.2.3.4")));
+    }
+
+    #[test]
+    fn blocklist_turns_away_listed_countries() {
+        let geo = blocker(&[("GEO_BLOCKED_COUNTRIES", "KP")]).unwrap();
+        assert!(geo.allows(ip("1.2.3.4")));
+        assert!(!geo.allows(ip("2.2.3.4")));
+    }
+
+    #[test]
+    fn unplaced_ips_are_let_through() {
+        let geo = blocker(&[("GEO_ALLOWED_COUNTRIES", "US")]).unwrap();
+        assert!(geo.allows(ip("3.3.3.3")));
+        assert!(geo.allows(ip("127.0.0.1")));
+        assert!(geo.allows(ip("::1")));
+    }
+
+    #[test]
+    fn geoblocking_needs_a_readable_database_and_a_country_list() {
+        assert!(blocker(&[]).is_none());
+
+        let no_path = Config::example(&[("GEO_BLOCKED_COUNTRIES", "KP")]).unwrap();
+        assert!(GeoBlocker::from_config(&no_path).is_none());
+
+        let missing = Config::example(&[
+            ("GEOIP_DB_PATH", "/nonexistent/GeoLite2-Country.mmdb"),
+            ("GEO_BLOCKED_COUNTRIES", "KP"),
+        ])
+        .unwrap();
+        assert!(GeoBlocker::from_config(&missing).is_none());
+    }
+
+    #[tokio::test]
+    async fn chat_requests_from_blocked_countries_are_refused() {
+        let upstream = Upstream::start().await;
+        let path = geoip_db(&[(1, "US"), (2, "KP")]);
+        let overrides = [
+            ("TRUST_PROXY", "true"),
+            ("GEOIP_DB_PATH", path.as_str()),
+            ("GEO_BLOCKED_COUNTRIES", "KP"),
+        ];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+
+        let from = |client_ip: &'static str| {
+            reqwest::Client::new()
+                .post(format!("http://{proxy}/chat/completions"))
+                .header("X-Forwarded-For", client_ip)
+                .json(&json!({ "messages": ping() }))
+                .send()
+        };
+
+        let blocked = from("2.2.3.4").await.unwrap();
+        assert_eq!(blocked.status(), StatusCode::FORBIDDEN);
+        assert!(upstream.requests().is_empty());
+
+        let allowed = from("1.2.3.4").await.unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+    }
+}
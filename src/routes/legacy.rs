@@ -1,37 +1,156 @@
-use axum::response::IntoResponse;
+use std::{sync::LazyLock, time::SystemTime};
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use utoipa::OpenApi;
+
+use crate::{ApiDoc, metrics::database::MetricsState};
+
+const GREETING: &str = "Hey there!";
+
+static VERSION: LazyLock<String> = LazyLock::new(|| ApiDoc::openapi().info.version);
 
 #[utoipa::path(
     get,
     path = "/model",
     responses(
-        (status = 200, description = "Comma-delimited allowed model list", content_type = "text/plain")
+        (status = 200, description = "Allowed models in their configured order, comma-delimited. With `Accept: application/json`, a JSON array of the allowed models followed by any model aliases",
+            content((String = "text/plain"), (Vec<String> = "application/json")))
     ),
     tag = "Legacy"
 )]
-pub async fn get_model() -> impl IntoResponse {
-    crate::ALLOWED_MODELS
+pub async fn get_model(State(state): State<MetricsState>, headers: HeaderMap) -> Response {
+    let models = state.allowed_models();
+
+    // Existing clients parse the CSV, so aliases are only listed in JSON.
+    if wants_json(&headers) {
+        let aliases = state.config.model_aliases.keys().cloned();
+        Json(models.into_iter().chain(aliases).collect::<Vec<_>>()).into_response()
+    } else {
+        models.join(",").into_response()
+    }
 }
 
 #[utoipa::path(
     get,
     path = "/echo",
     responses(
-        (status = 200, description = "Greeting message", content_type = "text/plain")
+        (status = 200, description = "Greeting message, or a JSON health payload with `Accept: application/json`",
+            content((String = "text/plain"), (serde_json::Value = "application/json",
+                example = json!({ "message": "Hey there!", "version": "0.0.1", "timestamp": "2025-01-01T00:00:00Z" }))))
     ),
     tag = "Legacy"
 )]
-pub async fn echo() -> impl IntoResponse {
-    "Hey there!"
+pub async fn echo(headers: HeaderMap) -> Response {
+    if !wants_json(&headers) {
+        return GREETING.into_response();
+    }
+
+    Json(json!({
+        "message": GREETING,
+        "version": *VERSION,
+        "timestamp": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+    }))
+    .into_response()
 }
 
 #[utoipa::path(
     get,
     path = "/hey",
     responses(
-        (status = 200, description = "Hello message", content_type = "text/plain")
+        (status = 200, description = "Hello message, or a JSON health payload with `Accept: application/json`",
+            content((String = "text/plain"), (serde_json::Value = "application/json")))
     ),
     tag = "Legacy"
 )]
-pub async fn manual_hello() -> impl IntoResponse {
-    echo().await
+pub async fn manual_hello(headers: HeaderMap) -> Response {
+    echo(headers).await
+}
+
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Upstream, state};
+    use axum::{body::to_bytes, http::HeaderValue};
+
+    async fn models(accept: Option<&'static str>) -> Vec<u8> {
+        let upstream = Upstream::start().await;
+        let response = get_model(State(state(&upstream, &[]).await), accepting(accept)).await;
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn model_csv_keeps_the_configured_allowlist() {
+        let csv = String::from_utf8(models(None).await).unwrap();
+        assert_eq!(
+            csv,
+            "qwen/qwen3-32b,openai/gpt-oss-120b,openai/gpt-oss-20b,meta-llama/llama-4-maverick-17b-128e-instruct"
+        );
+    }
+
+    #[tokio::test]
+    async fn model_json_lists_aliases_after_the_allowlist() {
+        let models: Vec<String> =
+            serde_json::from_slice(&models(Some("application/json")).await).unwrap();
+        assert_eq!(models.len(), 6);
+        assert_eq!(models[0], "qwen/qwen3-32b");
+
+        let mut aliases = models[4..].to_vec();
+        aliases.sort();
+        assert_eq!(aliases, ["llama", "qwen"]);
+    }
+
+    fn accepting(accept: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn echo_and_hey_stay_plain_text_by_default() {
+        for accept in [None, Some("text/plain, */*")] {
+            for response in [
+                echo(accepting(accept)).await,
+                manual_hello(accepting(accept)).await,
+            ] {
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                assert_eq!(body, GREETING);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_and_hey_return_a_health_payload_as_json() {
+        let json = Some("application/json");
+        for response in [
+            echo(accepting(json)).await,
+            manual_hello(accepting(json)).await,
+        ] {
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(payload["message"], GREETING);
+            assert_eq!(payload["version"], ApiDoc::openapi().info.version);
+            let timestamp = payload["timestamp"].as_str().unwrap();
+            assert!(humantime::parse_rfc3339(timestamp).is_ok(), "{timestamp}");
+        }
+    }
 }
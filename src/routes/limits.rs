@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    delegates::{
+        client_ip::ClientIp,
+        error::{APIError, ErrorKind},
+    },
+    metrics::database::MetricsState,
+    routes::completions::RequestMeta,
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Default)]
+pub struct RateLimiter {
+    inner: Mutex<RateWindow>,
+}
+
+#[derive(Default)]
+struct RateWindow {
+    started_at: u64,
+    counts: HashMap<(IpAddr, String), u32>,
+}
+
+impl RateLimiter {
+    pub fn check(&self, ip: IpAddr, model: &str, limit: u32, window: Duration) -> Result<(), u64> {
+        self.check_at(unix_secs(), ip, model, limit, window)
+    }
+
+    fn check_at(
+        &self,
+        now: u64,
+        ip: IpAddr,
+        model: &str,
+        limit: u32,
+        window: Duration,
+    ) -> Result<(), u64> {
+        let window = window.as_secs().max(1);
+        let started_at = now - now % window;
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.started_at != started_at {
+            inner.started_at = started_at;
+            inner.counts.clear();
+        }
+
+        let count = inner.counts.entry((ip, model.to_string())).or_default();
+        if *count >= limit {
+            return Err(started_at + window - now);
+        }
+
+        *count += 1;
+        Ok(())
+    }
+}
+
+pub async fn enforce_maintenance(
+    State(state): State<MetricsState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    check_maintenance(&state)?;
+
+    Ok(next.run(req).await)
+}
+
+pub fn check_maintenance(state: &MetricsState) -> Result<(), APIError> {
+    if state.maintenance.load(Ordering::Relaxed) {
+        return Err(APIError {
+            code: StatusCode::SERVICE_UNAVAILABLE,
+            kind: ErrorKind::Internal,
+            body: Some("Down for maintenance, please try again later".into()),
+        });
+    }
+
+    Ok(())
+}
+
+pub async fn enforce_rate_limit(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    let Some(model) = req
+        .extensions()
+        .get::<RequestMeta>()
+        .map(|meta| meta.resolved_model.as_str())
+    else {
+        return Ok(next.run(req).await);
+    };
+
+    if let Some(response) = rate_limited(&state, ip, model) {
+        return Ok(response);
+    }
+
+    Ok(next.run(req).await)
+}
+
+pub fn rate_limited(state: &MetricsState, ip: IpAddr, model: &str) -> Option<Response> {
+    let config = &state.config;
+    let limit = config
+        .model_rate_limits
+        .get(model)
+        .copied()
+        .unwrap_or(config.rate_limit_requests);
+    if limit == 0 {
+        return None;
+    }
+
+    let retry_in = state
+        .rate_limiter
+        .check(ip, model, limit, config.rate_limit_window)
+        .err()?;
+
+    Some(too_many_requests(
+        format!("Rate limit exceeded for {model}, try again in {retry_in} seconds"),
+        retry_in,
+    ))
+}
+
+pub async fn enforce_token_quota(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    if let Some(response) = quota_exceeded(&state, ip).await {
+        return Ok(response);
+    }
+
+    Ok(next.run(req).await)
+}
+
+pub async fn quota_exceeded(state: &MetricsState, ip: IpAddr) -> Option<Response> {
+    let quota = state.config.daily_token_quota;
+    if quota <= 0 {
+        return None;
+    }
+
+    let (_, used) = state.usage_for_ip(ip).await;
+    if used < quota {
+        return None;
+    }
+
+    let reset_in = SECS_PER_DAY - unix_secs() % SECS_PER_DAY;
+
+    Some(too_many_requests(
+        format!("Daily token quota exceeded, resets at 00:00 UTC (in {reset_in} seconds)"),
+        reset_in,
+    ))
+}
+
+pub fn too_many_requests(message: String, retry_after: u64) -> Response {
+    let mut response = APIError {
+        code: StatusCode::TOO_MANY_REQUESTS,
+        kind: ErrorKind::Client,
+        body: Some(message.into()),
+    }
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+
+    response
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{
+        Upstream, db_state, eventually, log_completion, ping, post_json, proxy, state,
+    };
+
+    const LOCALHOST: &str = "127.0.0.1";
+    const MODEL: &str = "qwen/qwen3-32b";
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn window_blocks_over_the_limit_then_resets() {
+        let limiter = RateLimiter::default();
+        let ip = LOCALHOST.parse().unwrap();
+
+        assert_eq!(limiter.check_at(120, ip, MODEL, 2, MINUTE), Ok(()));
+        assert_eq!(limiter.check_at(130, ip, MODEL, 2, MINUTE), Ok(()));
+        assert_eq!(limiter.check_at(150, ip, MODEL, 2, MINUTE), Err(30));
+        assert_eq!(limiter.check_at(180, ip, MODEL, 2, MINUTE), Ok(()));
+    }
+
+    #[test]
+    fn each_ip_and_model_has_its_own_count() {
+        let limiter = RateLimiter::default();
+        let ip = LOCALHOST.parse().unwrap();
+        let other_ip = "10.0.0.2".parse().unwrap();
+
+        assert_eq!(limiter.check_at(0, ip, MODEL, 1, MINUTE), Ok(()));
+        assert!(limiter.check_at(0, ip, MODEL, 1, MINUTE).is_err());
+        assert_eq!(
+            limiter.check_at(0, ip, "openai/gpt-oss-120b", 1, MINUTE),
+            Ok(())
+        );
+        assert_eq!(limiter.check_at(0, other_ip, MODEL, 1, MINUTE), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn model_rate_limits_override_the_global_limit() {
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("RATE_LIMIT_REQUESTS", "1"),
+            ("MODEL_RATE_LIMITS", r#"{"openai/gpt-oss-120b":2}"#),
+        ];
+        let state = state(&upstream, &overrides).await;
+        let ip = LOCALHOST.parse().unwrap();
+
+        assert!(rate_limited(&state, ip, "openai/gpt-oss-120b").is_none());
+        assert!(rate_limited(&state, ip, "openai/gpt-oss-120b").is_none());
+        assert!(rate_limited(&state, ip, "openai/gpt-oss-120b").is_some());
+
+        assert!(rate_limited(&state, ip, MODEL).is_none());
+        assert!(rate_limited(&state, ip, MODEL).is_some());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_get_429_with_retry_after() {
+        let upstream = Upstream::start().await;
+        let overrides = [
+            ("RATE_LIMIT_REQUESTS", "1"),
+            ("RATE_LIMIT_WINDOW_SECS", "3600"),
+        ];
+        let proxy = proxy(state(&upstream, &overrides).await).await;
+        let body = json!({ "messages": ping() });
+
+        let first = post_json(proxy, "/chat/completions", body.clone()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+
+        let second = post_json(proxy, "/chat/completions", body).await;
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = second.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=3600).contains(&retry_after));
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn over_quota_ips_get_429_until_midnight() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("DAILY_TOKEN_QUOTA", "10")]).await;
+        let caller = LOCALHOST.parse().unwrap();
+
+        log_completion(&state, caller, 12);
+        eventually(|| async { state.usage_for_ip(caller).await.1 == 12 }).await;
+
+        let proxy = proxy(state).await;
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=SECS_PER_DAY).contains(&retry_after));
+
+        let error: serde_json::Value = response.json().await.unwrap();
+        let message = error["error"]["message"].as_str().unwrap();
+        assert!(message.contains("resets at 00:00 UTC"), "{message}");
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn other_ips_keep_their_quota() {
+        let upstream = Upstream::start().await;
+        let state = db_state(&upstream, &[("DAILY_TOKEN_QUOTA", "10")]).await;
+        let other = "10.0.0.2".parse().unwrap();
+
+        log_completion(&state, other, 12);
+        eventually(|| async { state.usage_for_ip(other).await.1 == 12 }).await;
+
+        assert!(quota_exceeded(&state, other).await.is_some());
+        assert!(
+            quota_exceeded(&state, LOCALHOST.parse().unwrap())
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn quota_is_not_enforced_without_a_database() {
+        let upstream = Upstream::start().await;
+        let state = state(&upstream, &[("DAILY_TOKEN_QUOTA", "1")]).await;
+        log_completion(&state, LOCALHOST.parse().unwrap(), 12);
+
+        assert!(
+            quota_exceeded(&state, LOCALHOST.parse().unwrap())
+                .await
+                .is_none()
+        );
+    }
+}
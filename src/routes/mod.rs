@@ -1,2 +1,14 @@
+pub mod admin;
+pub mod batch;
+pub mod blocklist;
+pub mod compat;
 pub mod completions;
+pub mod geo;
 pub mod legacy;
+pub mod limits;
+pub mod not_found;
+pub mod schema;
+pub mod signature;
+pub mod transform;
+pub mod translate;
+pub mod websocket;
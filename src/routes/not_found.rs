@@ -0,0 +1,88 @@
+use axum::{
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use maud::html;
+
+use crate::delegates::error::{APIError, ErrorKind};
+
+pub async fn not_found(headers: HeaderMap) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    // Browsers ask for text/html first; anything else gets the JSON error.
+    if !accept.contains("text/html") || accept.contains("application/json") {
+        return APIError {
+            code: StatusCode::NOT_FOUND,
+            kind: ErrorKind::Client,
+            body: Some("Not Found".into()),
+        }
+        .into_response();
+    }
+
+    let page = html! {
+        html lang="en" {
+            head {
+                meta charset="UTF-8" {}
+                meta name="viewport" content="width=device-width, initial-scale=1.0" {}
+                title { "Not Found | Hack Club AI" }
+            }
+            body {
+                h1 { "404" }
+                p { "There's nothing here." }
+                p {
+                    a href="/" { "Back to ai.hackclub.com" }
+                    " or read the "
+                    a href="/docs" { "docs" }
+                    "."
+                }
+            }
+        }
+    };
+
+    (StatusCode::NOT_FOUND, Html(page.into_string())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn not_found_for(accept: Option<&str>) -> (String, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, accept.parse().unwrap());
+        }
+
+        let response = not_found(headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn browsers_get_an_html_page() {
+        let accept = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        let (content_type, body) = not_found_for(Some(accept)).await;
+        assert!(content_type.starts_with("text/html"), "{content_type}");
+        assert!(body.contains("<h1>404</h1>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn api_clients_get_the_json_error() {
+        for accept in [Some("application/json"), Some("*/*"), None] {
+            let (content_type, body) = not_found_for(accept).await;
+            assert_eq!(content_type, "application/json");
+            let error: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(error["error"]["message"], "Not Found");
+        }
+    }
+}
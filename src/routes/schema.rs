@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// A chat completion request as accepted by `/chat/completions`.
+///
+/// Only the commonly used fields are typed; anything else is kept in `extra`
+/// and forwarded to the upstream untouched.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[schema(example = json!({
+    "messages": [{"role": "user", "content": "Tell me a joke!"}]
+}))]
+pub struct ChatCompletionRequest {
+    /// Conversation so far, oldest message first.
+    pub messages: Vec<ChatMessage>,
+    /// Model ID or alias. Unknown models fall back to the default model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    /// Stream the response back as server-sent events. Also turned on by an
+    /// `Accept` header naming `text/event-stream`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// A single stop sequence or an array of them. Arrays longer than
+    /// `MAX_STOP_SEQUENCES` are truncated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub stop: Option<Value>,
+    /// Forwarded untouched for reproducible sampling and stored with the log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// One of `low`, `medium` or `high`; anything else is dropped, or
+    /// rejected with `STRICT_MODEL`. Stored with the log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub reasoning_effort: Option<Value>,
+    /// Arbitrary client data, forwarded untouched and stored with the log.
+    /// Rejected when larger than `MAX_METADATA_BYTES` serialized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Value>,
+    /// Dropped unless it is a tier listed in `ALLOWED_SERVICE_TIERS`,
+    /// including when it is not a string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub service_tier: Option<Value>,
+    /// Any other fields, forwarded as-is.
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChatMessage {
+    /// One of `system`, `user`, `assistant` or `tool`.
+    pub role: String,
+    /// Message text, or an array of content parts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub content: Option<Value>,
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn round_trips_without_losing_unknown_keys() {
+        let request = json!({
+            "messages": [
+                { "role": "user", "content": "hi", "name": "orpheus" },
+                { "role": "tool", "content": "42", "tool_call_id": "call_1" },
+            ],
+            "model": "qwen/qwen3-32b",
+            "temperature": 0.5,
+            "stream": false,
+            "stop": ["END"],
+            "service_tier": "flex",
+            "tools": [{ "type": "function", "function": { "name": "lookup" } }],
+            "parallel_tool_calls": true,
+        });
+
+        let typed: ChatCompletionRequest = serde_json::from_value(request.clone()).unwrap();
+        assert_eq!(typed.extra["parallel_tool_calls"], true);
+        assert_eq!(typed.messages[1].extra["tool_call_id"], "call_1");
+
+        assert_eq!(serde_json::to_value(&typed).unwrap(), request);
+    }
+
+    #[test]
+    fn requires_messages() {
+        let missing = serde_json::from_value::<ChatCompletionRequest>(json!({ "model": "qwen" }));
+        assert!(missing.is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    delegates::error::{APIError, ErrorKind},
+    metrics::database::MetricsState,
+};
+
+/// How far `X-Signature-Timestamp` may drift from the server clock before a
+/// signed request is treated as a replay.
+const SIGNATURE_TOLERANCE: Duration = Duration::from_secs(300);
+
+/// With HMAC_SECRET set, chat requests must carry `X-Signature-Timestamp`
+/// (unix seconds) and `X-Signature`: the hex HMAC-SHA256 of
+/// `{timestamp}.{raw body}`, optionally prefixed with `sha256=`. The
+/// websocket route has no body to sign and is refused instead.
+pub async fn verify_signature(
+    State(state): State<MetricsState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    let secret = &state.config.hmac_secret;
+    if secret.is_empty() || req.method() == Method::OPTIONS {
+        return Ok(next.run(req).await);
+    }
+
+    let timestamp = req
+        .headers()
+        .get("x-signature-timestamp")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|&timestamp| is_fresh(timestamp, SystemTime::now()));
+
+    let signature = req
+        .headers()
+        .get("x-signature")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().trim_start_matches("sha256="))
+        .and_then(|value| hex::decode(value).ok());
+
+    let (parts, body) = req.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.map_err(|_| APIError {
+        code: StatusCode::BAD_REQUEST,
+        kind: ErrorKind::Client,
+        body: Some("Failed to read request body".into()),
+    })?;
+
+    let valid = timestamp
+        .zip(signature)
+        .is_some_and(|(timestamp, signature)| {
+            sign(secret, timestamp, &bytes)
+                .verify_slice(&signature)
+                .is_ok()
+        });
+
+    if !valid {
+        return Err(APIError {
+            code: StatusCode::UNAUTHORIZED,
+            kind: ErrorKind::Client,
+            body: Some("Missing, stale or invalid X-Signature".into()),
+        });
+    }
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
+
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    mac
+}
+
+fn is_fresh(timestamp: u64, now: SystemTime) -> bool {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    now.abs_diff(timestamp) <= SIGNATURE_TOLERANCE.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{Upstream, ping, proxy, state};
+
+    const SECRET: &str = "s3cret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn signature(timestamp: u64, body: &[u8]) -> String {
+        hex::encode(sign(SECRET, timestamp, body).finalize().into_bytes())
+    }
+
+    async fn post_signed(
+        proxy: SocketAddr,
+        body: &[u8],
+        timestamp: u64,
+        signature: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("http://{proxy}/chat/completions"))
+            .header("content-type", "application/json")
+            .header("x-signature-timestamp", timestamp.to_string())
+            .header("x-signature", signature)
+            .body(body.to_vec())
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_signatures_are_accepted() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("HMAC_SECRET", SECRET)]).await).await;
+        let body = serde_json::to_vec(&json!({ "messages": ping() })).unwrap();
+
+        let signed = signature(now(), &body);
+        for header in [signed.clone(), format!("sha256={signed}")] {
+            let response = post_signed(proxy, &body, now(), &header).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(upstream.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn tampered_bodies_are_rejected() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("HMAC_SECRET", SECRET)]).await).await;
+        let body = serde_json::to_vec(&json!({ "messages": ping() })).unwrap();
+        let tampered = serde_json::to_vec(&json!({ "messages": ping(), "n": 8 })).unwrap();
+
+        let timestamp = now();
+        let response = post_signed(proxy, &tampered, timestamp, &signature(timestamp, &body)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let unsigned = reqwest::Client::new()
+            .post(format!("http://{proxy}/chat/completions"))
+            .json(&json!({ "messages": ping() }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(unsigned.status(), StatusCode::UNAUTHORIZED);
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stale_timestamps_are_rejected() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("HMAC_SECRET", SECRET)]).await).await;
+        let body = serde_json::to_vec(&json!({ "messages": ping() })).unwrap();
+
+        // A correctly signed request replayed after the tolerance window.
+        let stale = now() - SIGNATURE_TOLERANCE.as_secs() - 60;
+        let response = post_signed(proxy, &body, stale, &signature(stale, &body)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(upstream.requests().is_empty());
+    }
+
+    #[test]
+    fn freshness_allows_clock_skew_both_ways() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let tolerance = SIGNATURE_TOLERANCE.as_secs();
+
+        assert!(is_fresh(1_000_000 - tolerance, now));
+        assert!(is_fresh(1_000_000 + tolerance, now));
+        assert!(!is_fresh(1_000_000 - tolerance - 1, now));
+        assert!(!is_fresh(1_000_000 + tolerance + 1, now));
+    }
+}
@@ -0,0 +1,639 @@
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    config::MessagesOverflow, metrics::database::MetricsState,
+    routes::schema::ChatCompletionRequest,
+};
+
+const REASONING_EFFORTS: [&str; 3] = ["low", "medium", "high"];
+
+/// One step of request normalization. Forks can customize requests by
+/// implementing this and adding their transform to [`pipeline`].
+pub trait RequestTransform: Send + Sync {
+    /// Rewrites the request body in place, pushing anything that should
+    /// reject the request onto `problems`. Does nothing by default.
+    fn apply(&self, _state: &MetricsState, _json: &mut Value, _problems: &mut Vec<String>) {}
+}
+
+/// The transforms every chat request goes through, in order.
+pub fn pipeline() -> Vec<Box<dyn RequestTransform>> {
+    vec![Box::new(ModelDefaults), Box::new(Normalize)]
+}
+
+/// The built-in rules: parameter stripping, schema validation, aliases,
+/// message limits, metadata size, tool, response format, stop sequence and
+/// reasoning effort checks, temperature clamping and the model allowlist.
+pub struct Normalize;
+
+impl RequestTransform for Normalize {
+    fn apply(&self, state: &MetricsState, json: &mut Value, problems: &mut Vec<String>) {
+        let config = &state.config;
+
+        if let Some(obj) = json.as_object_mut() {
+            for param in &config.strip_params {
+                obj.remove(param);
+            }
+        }
+
+        if let Some(obj) = json.as_object_mut()
+            && let Some(model) = obj.get("model")
+            && !model.is_string()
+            && !model.is_null()
+        {
+            if config.strict_model {
+                problems.push("`model` must be a string".to_string());
+            } else {
+                warn!("Ignoring non-string model {model}, using the default model");
+            }
+            obj.remove("model");
+        }
+
+        if let Err(e) = ChatCompletionRequest::deserialize(&*json) {
+            problems.push(format!("Invalid request: {e}"));
+        }
+
+        if let Some(obj) = json.as_object_mut() {
+            let tier_allowed = obj
+                .get("service_tier")
+                .and_then(Value::as_str)
+                .is_some_and(|tier| config.allowed_service_tiers.iter().any(|t| t == tier));
+            if !tier_allowed {
+                obj.remove("service_tier");
+            }
+
+            if let Some(effort) = obj.get("reasoning_effort")
+                && !effort
+                    .as_str()
+                    .is_some_and(|e| REASONING_EFFORTS.contains(&e))
+            {
+                if config.strict_model {
+                    problems.push(format!(
+                        "`reasoning_effort` must be one of: {}",
+                        REASONING_EFFORTS.join(",")
+                    ));
+                } else {
+                    warn!("Dropping unknown reasoning_effort {effort}");
+                }
+                obj.remove("reasoning_effort");
+            }
+
+            if let Some(canonical) = obj
+                .get("model")
+                .and_then(Value::as_str)
+                .and_then(|m| config.resolve_model_alias(m))
+            {
+                obj.insert("model".to_string(), Value::String(canonical.to_string()));
+            }
+
+            if config.max_messages > 0
+                && let Some(messages) = obj.get_mut("messages").and_then(Value::as_array_mut)
+                && messages.len() > config.max_messages
+            {
+                match config.messages_overflow {
+                    MessagesOverflow::Reject => problems.push(format!(
+                        "Too many messages, at most {} are allowed",
+                        config.max_messages
+                    )),
+                    MessagesOverflow::Truncate => truncate_messages(messages, config.max_messages),
+                }
+            }
+
+            if let Some(tools) = obj.get("tools")
+                && let Err(problem) = validate_tools(tools)
+            {
+                problems.push(problem.to_string());
+            }
+
+            if let Some(format) = obj.get("response_format")
+                && let Err(problem) = validate_response_format(format)
+            {
+                problems.push(problem.to_string());
+            }
+
+            if config.max_metadata_bytes > 0
+                && let Some(metadata) = obj.get("metadata")
+                && metadata.to_string().len() > config.max_metadata_bytes
+            {
+                problems.push(format!(
+                    "`metadata` is too large, at most {} bytes are allowed",
+                    config.max_metadata_bytes
+                ));
+            }
+
+            if let Some(stop) = obj.get_mut("stop")
+                && let Err(problem) = limit_stop(stop, config.max_stop_sequences)
+            {
+                problems.push(problem.to_string());
+            }
+
+            if let Some(temperature) = obj.get("temperature").and_then(Value::as_f64) {
+                let clamped = config
+                    .min_temp
+                    .map_or(temperature, |min| temperature.max(min));
+                let clamped = config.max_temp.map_or(clamped, |max| clamped.min(max));
+                if clamped != temperature {
+                    obj.insert("temperature".to_string(), clamped.into());
+                }
+            }
+
+            let requested = obj.get("model").and_then(Value::as_str);
+            let needs_update = requested.is_none_or(|m| !state.is_allowed_model(m));
+
+            if needs_update {
+                if config.strict_model && requested.is_some() {
+                    problems.push(format!(
+                        "Unknown model, expected one of: {}",
+                        state.allowed_models().join(",")
+                    ));
+                }
+
+                obj.insert(
+                    "model".to_string(),
+                    Value::String(config.default_model.clone()),
+                );
+            }
+
+            if let Some(model) = obj.get("model").and_then(Value::as_str)
+                && config.json_unsupported_models.iter().any(|m| m == model)
+                && obj
+                    .get("response_format")
+                    .and_then(|f| f.get("type"))
+                    .is_some_and(|t| t != "text")
+            {
+                warn!("{model} does not support structured output, dropping response_format");
+                obj.remove("response_format");
+            }
+        }
+    }
+}
+
+/// Fills in MODEL_DEFAULTS for the model the request will resolve to,
+/// leaving any parameter the client sent untouched. It runs before
+/// [`Normalize`] so injected values get the same clamping and checks as the
+/// client's.
+pub struct ModelDefaults;
+
+impl RequestTransform for ModelDefaults {
+    fn apply(&self, state: &MetricsState, json: &mut Value, _problems: &mut Vec<String>) {
+        let config = &state.config;
+        let Some(obj) = json.as_object_mut() else {
+            return;
+        };
+
+        let requested = obj.get("model").and_then(Value::as_str);
+        let model = match requested.map(|m| config.resolve_model_alias(m).unwrap_or(m)) {
+            Some(model) if state.is_allowed_model(model) => model,
+            _ => &config.default_model,
+        };
+        let Some(defaults) = config.model_defaults.get(model) else {
+            return;
+        };
+
+        for (param, value) in defaults {
+            obj.entry(param.as_str()).or_insert_with(|| value.clone());
+        }
+    }
+}
+
+fn validate_response_format(format: &Value) -> Result<(), &'static str> {
+    if format.is_null() {
+        return Ok(());
+    }
+
+    match format.get("type").and_then(Value::as_str) {
+        Some("text" | "json_object" | "json_schema") => Ok(()),
+        _ => Err("`response_format.type` must be one of `text`, `json_object` or `json_schema`"),
+    }
+}
+
+/// Keeps at most `max` stop sequences (all of them when 0), since some
+/// providers reject longer lists.
+fn limit_stop(stop: &mut Value, max: usize) -> Result<(), &'static str> {
+    match stop {
+        Value::Null | Value::String(_) => Ok(()),
+        Value::Array(sequences) => {
+            if !sequences.iter().all(Value::is_string) {
+                return Err("Each `stop` sequence must be a string");
+            }
+            if max > 0 && sequences.len() > max {
+                warn!("Truncating {} stop sequences to {max}", sequences.len());
+                sequences.truncate(max);
+            }
+            Ok(())
+        }
+        _ => Err("`stop` must be a string or an array of strings"),
+    }
+}
+
+fn truncate_messages(messages: &mut Vec<Value>, max: usize) {
+    let excess = messages.len().saturating_sub(max);
+    let leading_system = messages
+        .first()
+        .and_then(|m| m.get("role"))
+        .and_then(Value::as_str)
+        == Some("system");
+
+    if leading_system && max > 1 {
+        messages.drain(1..=excess);
+    } else {
+        messages.drain(..excess);
+    }
+}
+
+/// Checks each tool is a named function. `null` means no tools, as the
+/// OpenAI SDKs send it.
+fn validate_tools(tools: &Value) -> Result<(), &'static str> {
+    if tools.is_null() {
+        return Ok(());
+    }
+    let tools = tools.as_array().ok_or("`tools` must be an array")?;
+
+    for tool in tools {
+        if tool.get("type").and_then(Value::as_str) != Some("function") {
+            return Err("Each tool must have `type: \"function\"`");
+        }
+
+        let has_name = tool
+            .get("function")
+            .and_then(|f| f.get("name"))
+            .and_then(Value::as_str)
+            .is_some_and(|name| !name.is_empty());
+        if !has_name {
+            return Err("Each tool must have a `function.name`");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        test_support::{Upstream, ping, post_json, proxy, state},
+    };
+
+    async fn normalized(json: Value) -> (Value, Vec<String>) {
+        normalized_with(&[], json).await
+    }
+
+    async fn normalized_with(overrides: &[(&str, &str)], mut json: Value) -> (Value, Vec<String>) {
+        let config = Arc::new(Config::example(overrides).unwrap());
+        let state = MetricsState::init(config, reqwest::Client::new(), Arc::default()).await;
+
+        let mut problems = Vec::new();
+        Normalize.apply(&state, &mut json, &mut problems);
+        (json, problems)
+    }
+
+    /// Tags every request and refuses ones that ask for more than 100 tokens.
+    struct Classroom;
+
+    impl RequestTransform for Classroom {
+        fn apply(&self, _state: &MetricsState, json: &mut Value, problems: &mut Vec<String>) {
+            if json["max_tokens"].as_u64().is_some_and(|n| n > 100) {
+                problems.push("Classroom requests are capped at 100 tokens".to_string());
+            }
+            if let Some(obj) = json.as_object_mut() {
+                obj.insert("user".to_string(), "classroom".into());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_transforms_join_the_pipeline() {
+        let upstream = Upstream::start().await;
+        let mut state = state(&upstream, &[]).await;
+        let mut transforms = pipeline();
+        transforms.push(Box::new(Classroom));
+        state.transforms = transforms.into();
+        let proxy = proxy(state).await;
+
+        let response = post_json(proxy, "/chat/completions", json!({ "messages": ping() })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let sent = upstream.requests();
+        assert_eq!(sent[0].body["user"], "classroom");
+        assert_eq!(sent[0].body["model"], "qwen/qwen3-32b");
+
+        let long = json!({ "messages": ping(), "max_tokens": 500 });
+        let response = post_json(proxy, "/chat/completions", long).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    /// Runs the whole default pipeline, as `validate_model` does.
+    async fn transformed_with(overrides: &[(&str, &str)], mut json: Value) -> (Value, Vec<String>) {
+        let config = Arc::new(Config::example(overrides).unwrap());
+        let state = MetricsState::init(config, reqwest::Client::new(), Arc::default()).await;
+
+        let mut problems = Vec::new();
+        for transform in pipeline() {
+            transform.apply(&state, &mut json, &mut problems);
+        }
+        (json, problems)
+    }
+
+    #[tokio::test]
+    async fn model_defaults_fill_in_per_model_values() {
+        let defaults = [(
+            "MODEL_DEFAULTS",
+            r#"{"qwen/qwen3-32b":{"temperature":0.6,"max_tokens":512},"meta-llama/llama-4-maverick-17b-128e-instruct":{"temperature":0.9}}"#,
+        )];
+
+        let (json, problems) = transformed_with(&defaults, json!({ "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(json["temperature"], 0.6);
+        assert_eq!(json["max_tokens"], 512);
+
+        let (json, _) =
+            transformed_with(&defaults, json!({ "model": "llama", "messages": [] })).await;
+        assert_eq!(json["temperature"], 0.9);
+        assert!(json.get("max_tokens").is_none());
+
+        let explicit = json!({ "messages": [], "temperature": 0.1 });
+        let (json, _) = transformed_with(&defaults, explicit).await;
+        assert_eq!(json["temperature"], 0.1);
+        assert_eq!(json["max_tokens"], 512);
+    }
+
+    #[tokio::test]
+    async fn model_defaults_are_clamped_and_checked_like_client_values() {
+        let overrides = [
+            ("MAX_TEMP", "1.5"),
+            (
+                "MODEL_DEFAULTS",
+                r#"{"qwen/qwen3-32b":{"temperature":5,"reasoning_effort":"extreme"}}"#,
+            ),
+        ];
+
+        let (json, problems) = transformed_with(&overrides, json!({ "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(json["temperature"], 1.5);
+        assert!(json.get("reasoning_effort").is_none());
+    }
+
+    #[tokio::test]
+    async fn service_tier_is_dropped_unless_allowed() {
+        let messages = json!([{ "role": "user", "content": "hi" }]);
+
+        let (json, problems) =
+            normalized(json!({ "messages": messages, "service_tier": "flex" })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(json["service_tier"], "flex");
+
+        for tier in [json!("scale"), json!(5), json!({ "tier": "flex" })] {
+            let (json, problems) =
+                normalized(json!({ "messages": messages, "service_tier": tier })).await;
+            assert!(problems.is_empty(), "{problems:?}");
+            assert!(json.get("service_tier").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn reasoning_effort_is_checked_against_the_known_levels() {
+        for effort in REASONING_EFFORTS {
+            let (json, problems) =
+                normalized(json!({ "messages": [], "reasoning_effort": effort })).await;
+            assert!(problems.is_empty(), "{problems:?}");
+            assert_eq!(json["reasoning_effort"], effort);
+        }
+
+        let (json, problems) = normalized(json!({ "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(json.get("reasoning_effort").is_none());
+
+        for effort in [json!("extreme"), json!(3)] {
+            let invalid = json!({ "messages": [], "reasoning_effort": effort });
+
+            let (json, problems) = normalized(invalid.clone()).await;
+            assert!(problems.is_empty(), "{problems:?}");
+            assert!(json.get("reasoning_effort").is_none());
+
+            let (_, problems) = normalized_with(&[("STRICT_MODEL", "true")], invalid).await;
+            assert_eq!(
+                problems,
+                ["`reasoning_effort` must be one of: low,medium,high"]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn aliases_resolve_to_the_canonical_model() {
+        let (json, problems) = normalized(json!({ "model": "llama", "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(
+            json["model"],
+            "meta-llama/llama-4-maverick-17b-128e-instruct"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_models_fall_back_to_the_default() {
+        let (json, problems) = normalized(json!({ "model": "gpt-4o", "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(json["model"], "qwen/qwen3-32b");
+    }
+
+    #[tokio::test]
+    async fn strict_mode_rejects_unknown_and_non_string_models() {
+        let strict = [("STRICT_MODEL", "true")];
+
+        let (_, problems) =
+            normalized_with(&strict, json!({ "model": "gpt-4o", "messages": [] })).await;
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("Unknown model"), "{problems:?}");
+
+        let (_, problems) = normalized_with(&strict, json!({ "model": 42, "messages": [] })).await;
+        assert_eq!(problems, ["`model` must be a string"]);
+    }
+
+    #[tokio::test]
+    async fn lenient_mode_replaces_non_string_models() {
+        let (json, problems) = normalized(json!({ "model": 42, "messages": [] })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(json["model"], "qwen/qwen3-32b");
+    }
+
+    fn conversation(turns: usize) -> Value {
+        let mut messages = vec![json!({ "role": "system", "content": "be brief" })];
+        messages.extend((0..turns).map(|i| json!({ "role": "user", "content": i.to_string() })));
+        Value::Array(messages)
+    }
+
+    #[tokio::test]
+    async fn overflow_reject_reports_the_limit() {
+        let limit = [("MAX_MESSAGES", "3"), ("MESSAGES_OVERFLOW", "reject")];
+
+        let (json, problems) =
+            normalized_with(&limit, json!({ "messages": conversation(3) })).await;
+        assert_eq!(problems, ["Too many messages, at most 3 are allowed"]);
+        assert_eq!(json["messages"].as_array().unwrap().len(), 4);
+
+        let (_, problems) = normalized_with(&limit, json!({ "messages": conversation(2) })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[tokio::test]
+    async fn overflow_truncate_keeps_the_system_message() {
+        let limit = [("MAX_MESSAGES", "3"), ("MESSAGES_OVERFLOW", "truncate")];
+
+        let (json, problems) =
+            normalized_with(&limit, json!({ "messages": conversation(4) })).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        let contents: Vec<&str> = json["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(contents, ["be brief", "2", "3"]);
+    }
+
+    fn roles(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn truncation_keeps_the_leading_system_message() {
+        let mut messages = vec![
+            json!({ "role": "system" }),
+            json!({ "role": "user" }),
+            json!({ "role": "assistant" }),
+            json!({ "role": "tool" }),
+        ];
+        truncate_messages(&mut messages, 2);
+        assert_eq!(roles(&messages), ["system", "tool"]);
+    }
+
+    #[test]
+    fn truncation_drops_the_oldest_messages() {
+        let mut messages = vec![
+            json!({ "role": "user" }),
+            json!({ "role": "assistant" }),
+            json!({ "role": "tool" }),
+        ];
+        truncate_messages(&mut messages, 2);
+        assert_eq!(roles(&messages), ["assistant", "tool"]);
+
+        // With room for one message only the newest survives, even over
+        // a leading system message.
+        let mut messages = vec![json!({ "role": "system" }), json!({ "role": "user" })];
+        truncate_messages(&mut messages, 1);
+        assert_eq!(roles(&messages), ["user"]);
+    }
+
+    #[test]
+    fn stop_lists_are_truncated_to_the_limit() {
+        let mut stop = json!(["a", "b", "c"]);
+        assert!(limit_stop(&mut stop, 2).is_ok());
+        assert_eq!(stop, json!(["a", "b"]));
+
+        let mut unlimited = json!(["a", "b", "c"]);
+        assert!(limit_stop(&mut unlimited, 0).is_ok());
+        assert_eq!(unlimited, json!(["a", "b", "c"]));
+    }
+
+    #[test]
+    fn stop_accepts_strings_and_null_only() {
+        assert!(limit_stop(&mut json!("END"), 1).is_ok());
+        assert!(limit_stop(&mut Value::Null, 1).is_ok());
+        assert!(limit_stop(&mut json!(["a", 1]), 4).is_err());
+        assert!(limit_stop(&mut json!(42), 4).is_err());
+    }
+
+    #[tokio::test]
+    async fn strip_params_removes_only_the_listed_keys() {
+        let strip = [("STRIP_PARAMS", "logit_bias, parallel_tool_calls")];
+        let request = json!({
+            "messages": [],
+            "logit_bias": { "50256": -100 },
+            "parallel_tool_calls": true,
+            "seed": 7,
+        });
+
+        let (json, problems) = normalized_with(&strip, request.clone()).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(json.get("logit_bias").is_none());
+        assert!(json.get("parallel_tool_calls").is_none());
+        assert_eq!(json["seed"], 7);
+
+        let (json, _) = normalized(request).await;
+        assert_eq!(json["parallel_tool_calls"], true);
+    }
+
+    #[tokio::test]
+    async fn temperature_is_clamped_into_the_configured_range() {
+        let range = [("MIN_TEMP", "0.2"), ("MAX_TEMP", "1.5")];
+        let clamp = |temperature: Value| async move {
+            let request = json!({ "messages": [], "temperature": temperature });
+            let (json, problems) = normalized_with(&range, request).await;
+            assert!(problems.is_empty(), "{problems:?}");
+            json["temperature"].clone()
+        };
+
+        assert_eq!(clamp(json!(50)).await, 1.5);
+        assert_eq!(clamp(json!(0)).await, 0.2);
+        assert_eq!(clamp(json!(0.7)).await, 0.7);
+
+        let (json, _) = normalized_with(&range, json!({ "messages": [] })).await;
+        assert!(json.get("temperature").is_none());
+    }
+
+    #[tokio::test]
+    async fn response_format_is_dropped_for_unsupported_models() {
+        let unsupported = [("JSON_UNSUPPORTED_MODELS", "openai/gpt-oss-20b")];
+        let request = |model: &str, kind: &str| json!({ "model": model, "messages": [], "response_format": { "type": kind } });
+
+        let (json, problems) =
+            normalized_with(&unsupported, request("openai/gpt-oss-20b", "json_object")).await;
+        assert!(problems.is_empty(), "{problems:?}");
+        assert!(json.get("response_format").is_none());
+
+        let (json, _) = normalized_with(&unsupported, request("openai/gpt-oss-20b", "text")).await;
+        assert_eq!(json["response_format"]["type"], "text");
+
+        let (json, _) =
+            normalized_with(&unsupported, request("qwen/qwen3-32b", "json_schema")).await;
+        assert_eq!(json["response_format"]["type"], "json_schema");
+    }
+
+    #[test]
+    fn response_format_accepts_known_types_and_null() {
+        for kind in ["text", "json_object", "json_schema"] {
+            assert!(validate_response_format(&json!({ "type": kind })).is_ok());
+        }
+        assert!(validate_response_format(&Value::Null).is_ok());
+        assert!(validate_response_format(&json!({ "type": "xml" })).is_err());
+        assert!(validate_response_format(&json!("json_object")).is_err());
+    }
+
+    #[test]
+    fn tools_accept_named_functions_and_null() {
+        let tools = json!([{ "type": "function", "function": { "name": "lookup" } }]);
+        assert!(validate_tools(&tools).is_ok());
+        assert!(validate_tools(&json!([])).is_ok());
+        assert!(validate_tools(&Value::Null).is_ok());
+    }
+
+    #[test]
+    fn tools_reject_malformed_entries() {
+        assert!(validate_tools(&json!({ "type": "function" })).is_err());
+        assert!(validate_tools(&json!([{ "type": "retrieval" }])).is_err());
+        assert!(validate_tools(&json!([{ "type": "function", "function": {} }])).is_err());
+        assert!(
+            validate_tools(&json!([{ "type": "function", "function": { "name": "" } }])).is_err()
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use serde_json::{Map, Value, json};
+
+pub fn anthropic_to_openai(request: &Value) -> Result<Value, String> {
+    let request = request
+        .as_object()
+        .ok_or("request body must be an object")?;
+
+    let mut messages = Vec::new();
+    if let Some(system) = request.get("system") {
+        messages.push(json!({ "role": "system", "content": text_of(system)? }));
+    }
+
+    for message in request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("`messages` must be an array")?
+    {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .ok_or("each message must have a `role`")?;
+        let content = message
+            .get("content")
+            .ok_or("each message must have `content`")?;
+        messages.push(json!({ "role": role, "content": content_of(content)? }));
+    }
+
+    let mut translated = Map::new();
+    translated.insert("messages".to_string(), Value::Array(messages));
+    for (from, to) in [
+        ("model", "model"),
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop_sequences", "stop"),
+    ] {
+        if let Some(value) = request.get(from) {
+            translated.insert(to.to_string(), value.clone());
+        }
+    }
+
+    Ok(Value::Object(translated))
+}
+
+pub fn openai_to_anthropic(response: &Value) -> Value {
+    let choice = response.get("choices").and_then(|c| c.get(0));
+    let text = choice
+        .and_then(|c| c.get("message"))
+        .and_then(|m| m.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let stop_reason = match choice
+        .and_then(|c| c.get("finish_reason"))
+        .and_then(Value::as_str)
+    {
+        Some("length") => "max_tokens",
+        Some("tool_calls") => "tool_use",
+        _ => "end_turn",
+    };
+    let usage = response.get("usage");
+    let count = |key: &str| usage.and_then(|u| u.get(key)).cloned().unwrap_or(json!(0));
+
+    json!({
+        "id": response.get("id"),
+        "type": "message",
+        "role": "assistant",
+        "model": response.get("model"),
+        "content": [{ "type": "text", "text": text }],
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": count("prompt_tokens"),
+            "output_tokens": count("completion_tokens"),
+        },
+    })
+}
+
+pub fn completion_to_chat(request: &Value) -> Result<Value, String> {
+    let request = request
+        .as_object()
+        .ok_or("request body must be an object")?;
+
+    let prompt = match request.get("prompt") {
+        Some(Value::String(prompt)) => prompt.clone(),
+        Some(Value::Array(prompts)) if prompts.len() == 1 => prompts[0]
+            .as_str()
+            .ok_or("`prompt` must be a string")?
+            .to_string(),
+        Some(Value::Array(_)) => return Err("only a single prompt is supported".to_string()),
+        _ => return Err("`prompt` must be a string".to_string()),
+    };
+
+    let mut translated = Map::new();
+    translated.insert(
+        "messages".to_string(),
+        json!([{ "role": "user", "content": prompt }]),
+    );
+    for key in [
+        "model",
+        "max_tokens",
+        "temperature",
+        "top_p",
+        "stop",
+        "seed",
+        "presence_penalty",
+        "frequency_penalty",
+        "user",
+    ] {
+        if let Some(value) = request.get(key) {
+            translated.insert(key.to_string(), value.clone());
+        }
+    }
+
+    Ok(Value::Object(translated))
+}
+
+pub fn chat_to_completion(response: &Value) -> Value {
+    let choices: Vec<Value> = response
+        .get("choices")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|choice| {
+            json!({
+                "text": choice
+                    .get("message")
+                    .and_then(|m| m.get("content"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                "index": choice.get("index"),
+                "logprobs": null,
+                "finish_reason": choice.get("finish_reason"),
+            })
+        })
+        .collect();
+
+    json!({
+        "id": response.get("id"),
+        "object": "text_completion",
+        "created": response.get("created"),
+        "model": response.get("model"),
+        "choices": choices,
+        "usage": response.get("usage"),
+    })
+}
+
+fn text_of(content: &Value) -> Result<String, String> {
+    match content {
+        Value::String(text) => Ok(text.clone()),
+        Value::Array(blocks) => blocks
+            .iter()
+            .map(|block| match block.get("type").and_then(Value::as_str) {
+                Some("text") => Ok(block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()),
+                other => Err(format!("unsupported system block type {other:?}")),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|parts| parts.join("\n")),
+        _ => Err("`system` must be a string or an array of text blocks".to_string()),
+    }
+}
+
+fn content_of(content: &Value) -> Result<Value, String> {
+    let Value::Array(blocks) = content else {
+        return Ok(content.clone());
+    };
+
+    blocks
+        .iter()
+        .map(|block| match block.get("type").and_then(Value::as_str) {
+            Some("text") => Ok(json!({ "type": "text", "text": block.get("text") })),
+            Some("image") => {
+                let source = block.get("source").ok_or("image block without `source`")?;
+                let url = match source.get("type").and_then(Value::as_str) {
+                    Some("base64") => format!(
+                        "data:{};base64,{}",
+                        source
+                            .get("media_type")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default(),
+                        source
+                            .get("data")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                    ),
+                    Some("url") => source
+                        .get("url")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    other => return Err(format!("unsupported image source type {other:?}")),
+                };
+                Ok(json!({ "type": "image_url", "image_url": { "url": url } }))
+            }
+            other => Err(format!("unsupported content block type {other:?}")),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Value::Array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anthropic_requests_become_chat_requests() {
+        let request = json!({
+            "model": "qwen",
+            "max_tokens": 64,
+            "stop_sequences": ["END"],
+            "system": [{ "type": "text", "text": "Be brief." }, { "type": "text", "text": "Be kind." }],
+            "messages": [
+                { "role": "user", "content": "hi" },
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "text", "text": "what is this?" },
+                        { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } },
+                    ],
+                },
+            ],
+        });
+
+        let translated = anthropic_to_openai(&request).unwrap();
+        assert_eq!(
+            translated,
+            json!({
+                "model": "qwen",
+                "max_tokens": 64,
+                "stop": ["END"],
+                "messages": [
+                    { "role": "system", "content": "Be brief.\nBe kind." },
+                    { "role": "user", "content": "hi" },
+                    {
+                        "role": "user",
+                        "content": [
+                            { "type": "text", "text": "what is this?" },
+                            { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                        ],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn anthropic_requests_reject_unsupported_blocks() {
+        let tool_use = json!({
+            "messages": [{ "role": "user", "content": [{ "type": "tool_use" }] }],
+        });
+        assert!(anthropic_to_openai(&tool_use).is_err());
+        assert!(anthropic_to_openai(&json!({ "messages": "hi" })).is_err());
+        assert!(anthropic_to_openai(&json!([])).is_err());
+    }
+
+    #[test]
+    fn chat_responses_become_anthropic_messages() {
+        let response = json!({
+            "id": "chatcmpl-1",
+            "model": "qwen/qwen3-32b",
+            "choices": [{ "message": { "content": "hello" }, "finish_reason": "length" }],
+            "usage": { "prompt_tokens": 5, "completion_tokens": 2 },
+        });
+
+        let message = openai_to_anthropic(&response);
+        assert_eq!(
+            message["content"],
+            json!([{ "type": "text", "text": "hello" }])
+        );
+        assert_eq!(message["stop_reason"], "max_tokens");
+        assert_eq!(
+            message["usage"],
+            json!({ "input_tokens": 5, "output_tokens": 2 })
+        );
+    }
+
+    #[test]
+    fn prompts_become_a_single_user_message() {
+        let request = json!({ "model": "qwen", "prompt": ["Say hi"], "seed": 7, "echo": true });
+
+        let translated = completion_to_chat(&request).unwrap();
+        assert_eq!(
+            translated,
+            json!({
+                "model": "qwen",
+                "seed": 7,
+                "messages": [{ "role": "user", "content": "Say hi" }],
+            })
+        );
+    }
+
+    #[test]
+    fn prompts_must_be_a_single_string() {
+        assert!(completion_to_chat(&json!({ "prompt": ["a", "b"] })).is_err());
+        assert!(completion_to_chat(&json!({ "prompt": [1] })).is_err());
+        assert!(completion_to_chat(&json!({})).is_err());
+    }
+
+    #[test]
+    fn chat_responses_become_text_completions() {
+        let response = json!({
+            "id": "chatcmpl-1",
+            "created": 1,
+            "model": "qwen/qwen3-32b",
+            "choices": [{ "index": 0, "message": { "content": "hi" }, "finish_reason": "stop" }],
+            "usage": { "total_tokens": 3 },
+        });
+
+        let completion = chat_to_completion(&response);
+        assert_eq!(completion["object"], "text_completion");
+        assert_eq!(
+            completion["choices"],
+            json!([{ "text": "hi", "index": 0, "logprobs": null, "finish_reason": "stop" }])
+        );
+        assert_eq!(completion["usage"], response["usage"]);
+    }
+}
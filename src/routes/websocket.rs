@@ -0,0 +1,207 @@
+use std::net::IpAddr;
+
+use axum::{
+    body::to_bytes,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde_json::Value;
+
+use crate::{
+    delegates::{
+        client_ip::ClientIp,
+        error::{APIError, ErrorKind},
+    },
+    metrics::database::MetricsState,
+    routes::completions::complete_json,
+};
+
+const PATH: &str = "/ws/chat/completions";
+const DONE: &str = "[DONE]";
+
+pub async fn ws_completions(
+    State(state): State<MetricsState>,
+    ClientIp(ip): ClientIp,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // Frames can't carry an X-Signature, so signed deployments refuse sockets
+    // rather than let them bypass HMAC_SECRET.
+    if !state.config.hmac_secret.is_empty() {
+        return APIError {
+            code: StatusCode::UNAUTHORIZED,
+            kind: ErrorKind::Client,
+            body: Some(
+                "Websocket completions are unavailable while requests must be signed".into(),
+            ),
+        }
+        .into_response();
+    }
+
+    ws.on_upgrade(move |socket| serve_socket(socket, state, ip))
+}
+
+async fn serve_socket(mut socket: WebSocket, state: MetricsState, ip: IpAddr) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let response = run_completion(&state, ip, text.as_str())
+            .await
+            .unwrap_or_else(IntoResponse::into_response);
+        if forward(&mut socket, response).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn run_completion(
+    state: &MetricsState,
+    ip: IpAddr,
+    text: &str,
+) -> Result<Response, APIError> {
+    let mut json: Value = serde_json::from_str(text).map_err(|_| APIError {
+        code: StatusCode::BAD_REQUEST,
+        kind: ErrorKind::Client,
+        body: Some("Invalid JSON".into()),
+    })?;
+
+    if let Some(obj) = json.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(true));
+    }
+
+    complete_json(state, ip, "GET", PATH, text.len(), json).await
+}
+
+async fn forward(socket: &mut WebSocket, response: Response) -> Result<(), axum::Error> {
+    if !response.status().is_success() {
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        return socket.send(text_frame(&body)).await;
+    }
+
+    let mut stream = response.into_body().into_data_stream();
+    let mut pending = Vec::new();
+
+    while let Some(Ok(chunk)) = stream.next().await {
+        pending.extend_from_slice(&chunk);
+
+        let mut start = 0;
+        while let Some(len) = pending[start..].iter().position(|&b| b == b'\n') {
+            let line = &pending[start..start + len];
+            start += len + 1;
+
+            if let Some(data) = line.strip_prefix(b"data:").map(<[u8]>::trim_ascii)
+                && data != DONE.as_bytes()
+            {
+                socket.send(text_frame(data)).await?;
+            }
+        }
+
+        pending.drain(..start);
+    }
+
+    socket.send(Message::Text(DONE.into())).await
+}
+
+fn text_frame(data: &[u8]) -> Message {
+    Message::Text(String::from_utf8_lossy(data).into_owned().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::SinkExt;
+    use serde_json::json;
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    use super::*;
+    use crate::test_support::{Upstream, chunk, event_stream, ping, proxy, state};
+
+    #[tokio::test]
+    async fn streams_chunks_as_frames_then_done() {
+        let upstream = Upstream::responding(|body| async move {
+            event_stream(vec![
+                (Duration::ZERO, chunk(&body["model"], "po").to_string()),
+                (Duration::ZERO, chunk(&body["model"], "ng").to_string()),
+                (Duration::ZERO, DONE.to_string()),
+            ])
+        })
+        .await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+
+        let (mut socket, _) = connect_async(format!("ws://{proxy}{PATH}")).await.unwrap();
+        let request = json!({ "messages": ping() }).to_string();
+        socket
+            .send(tungstenite::Message::text(request))
+            .await
+            .unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(Ok(tungstenite::Message::Text(text))) = socket.next().await {
+            let done = text.as_str() == DONE;
+            frames.push(text.to_string());
+            if done {
+                break;
+            }
+        }
+
+        assert_eq!(frames.len(), 3, "{frames:?}");
+        let content = |frame: &str| {
+            let chunk: Value = serde_json::from_str(frame).unwrap();
+            chunk["choices"][0]["delta"]["content"].clone()
+        };
+        assert_eq!(content(&frames[0]), "po");
+        assert_eq!(content(&frames[1]), "ng");
+        assert_eq!(upstream.requests()[0].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn bad_frames_get_an_error_and_the_socket_stays_open() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[]).await).await;
+        let (mut socket, _) = connect_async(format!("ws://{proxy}{PATH}")).await.unwrap();
+
+        socket
+            .send(tungstenite::Message::text("not json"))
+            .await
+            .unwrap();
+        let Some(Ok(tungstenite::Message::Text(error))) = socket.next().await else {
+            panic!("no error frame");
+        };
+        let error: Value = serde_json::from_str(error.as_str()).unwrap();
+        assert_eq!(error["error"]["message"], "Invalid JSON");
+
+        let request = json!({ "messages": ping() }).to_string();
+        socket
+            .send(tungstenite::Message::text(request))
+            .await
+            .unwrap();
+        assert!(matches!(
+            socket.next().await,
+            Some(Ok(tungstenite::Message::Text(_)))
+        ));
+        assert_eq!(upstream.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn signed_deployments_refuse_sockets() {
+        let upstream = Upstream::start().await;
+        let proxy = proxy(state(&upstream, &[("HMAC_SECRET", "s3cret")]).await).await;
+
+        let refused = connect_async(format!("ws://{proxy}{PATH}")).await;
+        let Err(tungstenite::Error::Http(response)) = refused else {
+            panic!("the upgrade was accepted");
+        };
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
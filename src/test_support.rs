@@ -0,0 +1,430 @@
+//! Scaffolding shared by the in-file tests: scratch servers, a recording
+//! mock upstream and proxy state built from `.env.example`.
+
+use std::{
+    convert::Infallible,
+    env,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use futures::{StreamExt, future::BoxFuture, stream};
+use serde_json::{Value, json};
+use tokio::{net::TcpListener, time::sleep};
+use tokio_postgres::{NoTls, Row};
+use tracing::{Level, subscriber::DefaultGuard};
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use crate::{
+    app,
+    config::Config,
+    delegates::upstream::{ConnectionStats, build_client},
+    metrics::database::{LogEntry, MetricsState},
+    run_migrations,
+};
+
+/// The default mock upstream answers this model with a 503.
+pub const BROKEN_MODEL: &str = "openai/gpt-oss-20b";
+
+pub async fn serve(router: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+/// One completion request as the upstream received it.
+#[derive(Clone)]
+pub struct Recorded {
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+type Requests = Arc<Mutex<Vec<Recorded>>>;
+type Responder = Arc<dyn Fn(Value) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// A stand-in for the provider that records every completion it receives.
+pub struct Upstream {
+    pub addr: SocketAddr,
+    requests: Requests,
+}
+
+impl Upstream {
+    /// Answers every completion with "pong", except for BROKEN_MODEL.
+    pub async fn start() -> Self {
+        Self::responding(|body| async move {
+            if body["model"] == BROKEN_MODEL {
+                let error = json!({ "error": { "message": "overloaded" } });
+                return (StatusCode::SERVICE_UNAVAILABLE, Json(error)).into_response();
+            }
+            Json(completion(&body["model"], "pong")).into_response()
+        })
+        .await
+    }
+
+    pub async fn responding<F, Fut>(respond: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let requests = Requests::default();
+        let respond: Responder = Arc::new(move |body| Box::pin(respond(body)));
+        let models = || async { Json(json!({ "object": "list", "data": [] })) };
+
+        let router = Router::new()
+            .route("/v1/chat/completions", post(record))
+            .route("/v1/models", get(models))
+            .with_state((requests.clone(), respond));
+
+        Self {
+            addr: serve(router).await,
+            requests,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/v1/chat/completions", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The `model` of each completion received, in order.
+    pub fn models(&self) -> Vec<Value> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|r| r.body["model"].clone()).collect()
+    }
+}
+
+async fn record(
+    State((requests, respond)): State<(Requests, Responder)>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    requests.lock().unwrap().push(Recorded {
+        headers,
+        body: body.clone(),
+    });
+    respond(body).await
+}
+
+pub fn completion(model: &Value, content: &str) -> Value {
+    json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop",
+        }],
+        "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 },
+    })
+}
+
+pub fn chunk(model: &Value, content: &str) -> Value {
+    json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion.chunk",
+        "model": model,
+        "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }],
+    })
+}
+
+/// An SSE response that sends each event's `data` after its delay.
+pub fn event_stream(events: Vec<(Duration, String)>) -> Response {
+    let events = stream::iter(events).then(|(delay, data)| async move {
+        sleep(delay).await;
+        Ok::<_, Infallible>(format!("data: {data}\n\n"))
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from_stream(events))
+        .unwrap()
+}
+
+/// State from `.env.example` plus `overrides`, sending completions to
+/// `upstream`.
+pub async fn state(upstream: &Upstream, overrides: &[(&str, &str)]) -> MetricsState {
+    let url = upstream.url();
+    let mut vars = vec![("COMPLETIONS_URL", url.as_str())];
+    vars.extend_from_slice(overrides);
+
+    let config = Arc::new(Config::example(&vars).unwrap());
+    let connections = Arc::new(ConnectionStats::default());
+    let client = build_client(&config, connections.clone()).unwrap();
+    MetricsState::init(config, client, connections).await
+}
+
+/// Serves the full app for `state`.
+pub async fn proxy(state: MetricsState) -> SocketAddr {
+    serve(app(state)).await
+}
+
+pub async fn post_json(proxy: SocketAddr, path: &str, body: Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("http://{proxy}{path}"))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+pub fn ping() -> Value {
+    json!([{ "role": "user", "content": "ping" }])
+}
+
+/// A fresh database on the server at TEST_DATABASE_URL. Tests using it are
+/// marked `#[ignore = "needs TEST_DATABASE_URL"]`.
+pub async fn database() -> String {
+    let server = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    let (client, connection) = tokio_postgres::connect(&server, NoTls).await.unwrap();
+    tokio::spawn(connection);
+
+    let name = format!("hackclub_ai_test_{:016x}", rand::random::<u64>());
+    client
+        .batch_execute(&format!("CREATE DATABASE {name}"))
+        .await
+        .unwrap();
+
+    let (base, _) = server.rsplit_once('/').unwrap();
+    format!("{base}/{name}")
+}
+
+/// Like [`state`], backed by a fresh, migrated [`database`].
+pub async fn db_state(upstream: &Upstream, overrides: &[(&str, &str)]) -> MetricsState {
+    let url = database().await;
+    let mut vars = vec![("DATABASE_URL", url.as_str())];
+    vars.extend_from_slice(overrides);
+
+    let state = state(upstream, &vars).await;
+    run_migrations(&state).await;
+    state
+}
+
+/// Waits for `count` rows to land in `api_logs`, returning them oldest first.
+pub async fn logged_rows(state: &MetricsState, count: usize) -> Vec<Row> {
+    let client = state.db.as_ref().unwrap().get().await.unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let rows = client
+            .query("SELECT * FROM api_logs ORDER BY id", &[])
+            .await
+            .unwrap();
+        if rows.len() >= count {
+            assert_eq!(rows.len(), count, "more rows were logged than expected");
+            return rows;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "only {} of {count} rows logged within 5s",
+            rows.len()
+        );
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Logs a completion from `ip` that used `tokens`.
+pub fn log_completion(state: &MetricsState, ip: IpAddr, tokens: i32) {
+    let request = json!({ "model": "qwen/qwen3-32b", "messages": ping() });
+    let response = completion(&request["model"], "pong");
+
+    state.log_request(LogEntry {
+        request: &request,
+        response: &response,
+        ip,
+        tokens: Some(tokens),
+        token_source: None,
+        method: "POST",
+        path: "/chat/completions",
+        req_bytes: 0,
+        resp_bytes: 0,
+        requested_model: None,
+        resolved_model: "qwen/qwen3-32b",
+        latency: Duration::ZERO,
+        finish_reason: Some("stop"),
+        completed: true,
+    });
+}
+
+/// Polls `check` until it holds, for effects that land in the background
+/// such as log writes.
+pub async fn eventually<F, Fut>(mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !check().await {
+        assert!(Instant::now() < deadline, "condition not met within 5s");
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// Writes a GeoIP2 Country database that places each IPv4 `/8` network in
+/// the given country, returning its path.
+pub fn geoip_db(networks: &[(u8, &str)]) -> String {
+    enum Record {
+        Empty,
+        Node(usize),
+        Data(usize),
+    }
+
+    let mut nodes = vec![[Record::Empty, Record::Empty]];
+    let mut data = Vec::new();
+    for &(octet, iso_code) in networks {
+        let offset = data.len();
+        mmdb_map(&mut data, 1);
+        mmdb_str(&mut data, "country");
+        mmdb_map(&mut data, 1);
+        mmdb_str(&mut data, "iso_code");
+        mmdb_str(&mut data, iso_code);
+
+        let mut node = 0;
+        for depth in 0..8 {
+            let bit = usize::from((octet >> (7 - depth)) & 1);
+            if depth == 7 {
+                nodes[node][bit] = Record::Data(offset);
+            } else if let Record::Node(next) = nodes[node][bit] {
+                node = next;
+            } else {
+                nodes.push([Record::Empty, Record::Empty]);
+                nodes[node][bit] = Record::Node(nodes.len() - 1);
+                node = nodes.len() - 1;
+            }
+        }
+    }
+
+    // 24-bit records: a node index, "not found", or a data section offset.
+    let node_count = nodes.len();
+    let mut db = Vec::new();
+    for record in nodes.iter().flatten() {
+        let value = match *record {
+            Record::Empty => node_count,
+            Record::Node(next) => next,
+            Record::Data(offset) => node_count + 16 + offset,
+        };
+        db.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+    }
+    db.extend_from_slice(&[0; 16]);
+    db.extend_from_slice(&data);
+
+    db.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+    mmdb_map(&mut db, 9);
+    for (key, value) in [
+        ("binary_format_major_version", 2),
+        ("binary_format_minor_version", 0),
+    ] {
+        mmdb_str(&mut db, key);
+        mmdb_u16(&mut db, value);
+    }
+    mmdb_str(&mut db, "build_epoch");
+    // uint64 and array are extended types, numbered past the control byte.
+    db.extend_from_slice(&[0x08, 0x02]);
+    db.extend_from_slice(&0u64.to_be_bytes());
+    mmdb_str(&mut db, "database_type");
+    mmdb_str(&mut db, "GeoIP2-Country");
+    mmdb_str(&mut db, "description");
+    mmdb_map(&mut db, 0);
+    mmdb_str(&mut db, "ip_version");
+    mmdb_u16(&mut db, 4);
+    mmdb_str(&mut db, "languages");
+    db.extend_from_slice(&[0x00, 0x04]);
+    mmdb_str(&mut db, "node_count");
+    db.push(0xC4); // uint32
+    db.extend_from_slice(&(node_count as u32).to_be_bytes());
+    mmdb_str(&mut db, "record_size");
+    mmdb_u16(&mut db, 24);
+
+    let path = env::temp_dir().join(format!(
+        "hackclub_ai_test_{:016x}.mmdb",
+        rand::random::<u64>()
+    ));
+    std::fs::write(&path, db).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn mmdb_map(out: &mut Vec<u8>, entries: u8) {
+    out.push(0xE0 | entries);
+}
+
+fn mmdb_str(out: &mut Vec<u8>, value: &str) {
+    out.push(0x40 | value.len() as u8);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn mmdb_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(0xA2);
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Log output formatted on this thread while it is alive. Tests run on a
+/// current-thread runtime, so that includes servers they spawn.
+pub struct Logs {
+    output: Arc<Mutex<Vec<u8>>>,
+    _guard: DefaultGuard,
+}
+
+pub fn capture_logs() -> Logs {
+    let output = Arc::<Mutex<Vec<u8>>>::default();
+    let sink = output.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_max_level(Level::TRACE)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(move || LogSink(sink.clone()))
+        .finish();
+
+    Logs {
+        output,
+        _guard: tracing::subscriber::set_default(subscriber),
+    }
+}
+
+impl Logs {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned()
+    }
+
+    /// The lines that contain every one of `needles`.
+    pub fn lines_with(&self, needles: &[&str]) -> Vec<String> {
+        self.contents()
+            .lines()
+            .filter(|line| needles.iter().all(|needle| line.contains(needle)))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+struct LogSink(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}